@@ -0,0 +1,60 @@
+use std::time::{Duration, Instant};
+
+use barnes_hut::{utils, Body, Float, Quad, Quadtree};
+
+const RUNS: u32 = 10;
+
+fn time(mut f: impl FnMut()) -> Duration {
+    f();
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        f();
+    }
+    start.elapsed() / RUNS
+}
+
+fn insert(quadtree: &mut Quadtree, bodies: &[Body]) {
+    quadtree.clear(Quad::new_containing(bodies));
+    for body in bodies {
+        quadtree.insert(body);
+    }
+    quadtree.propagate();
+}
+
+fn forces(quadtree: &Quadtree, bodies: &[Body]) -> Float {
+    bodies.iter().map(|body| quadtree.acc(body.pos).x).sum()
+}
+
+fn main() {
+    for n in [10_000, 100_000, 1_000_000] {
+        let mut bodies = utils::uniform_disc(n, 0);
+        // Shuffle so the insert loop sees bodies in no particular spatial order, as it does mid-run
+        fastrand::shuffle(&mut bodies);
+
+        let mut quadtree = Quadtree::new(1.0, 1.0);
+        let insert_build = time(|| insert(&mut quadtree, &bodies));
+        let insert_acc = time(|| {
+            std::hint::black_box(forces(&quadtree, &bodies));
+        });
+
+        let cells_build = time(|| quadtree.build_parallel(&bodies, 0));
+        let parallel_build = time(|| quadtree.build_morton_parallel(&bodies, 0));
+        let morton_build = time(|| quadtree.build_morton(&bodies));
+
+        // What `Simulation` does with `reorder_bodies`: evaluate forces in Morton order
+        let sorted = quadtree
+            .points
+            .iter()
+            .map(|p| bodies[p.index])
+            .collect::<Vec<_>>();
+        let morton_acc = time(|| {
+            std::hint::black_box(forces(&quadtree, &sorted));
+        });
+
+        println!("n = {n}");
+        println!("  insert: build {insert_build:>12?}  forces {insert_acc:>12?}");
+        println!("  morton: build {morton_build:>12?}  forces {morton_acc:>12?}");
+        println!("  insert cells, all threads: build {cells_build:>12?}");
+        println!("  morton, all threads: build {parallel_build:>12?}");
+    }
+}
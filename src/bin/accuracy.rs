@@ -0,0 +1,199 @@
+use barnes_hut::{
+    direct::{DirectSum, ErrorStats},
+    parallel, Float, Fmm, FmmConfig, GravitySolver, Opening, Quadtree, SimulationConfig,
+};
+
+const USAGE: &str = "\
+Usage: accuracy [OPTIONS]
+
+Compares Barnes-Hut accelerations against exact direct summation for a range of opening angles,
+and FMM accelerations for a range of expansion orders.
+
+Options:
+  --config <FILE>          Load parameters from a .toml or .ron config file, other options override it
+  --n <N>                  Number of bodies [default: 20000]
+  --epsilon <EPSILON>      Softening length [default: 1.0]
+  --softening <KERNEL>     Softening kernel: plummer, spline or none [default: plummer]
+  --thetas <LIST>          Comma-separated opening angles [default: 0.3,0.5,0.7,1.0]
+  --opening <NAME>         Opening criterion: geometric, bmax or relative [default: geometric]
+  --alpha <ALPHA>          Tolerance of the relative opening criterion [default: 0.005]
+  --box-guard <BOOL>       Always open nodes whose cell contains the body
+                           [default: true for bmax and relative, false for geometric]
+  --fmm-orders <LIST>      Comma-separated FMM expansion orders, empty to skip [default: 2,4,6]
+  --multipole <NAME>       monopole or quadrupole [default: monopole]
+  --leaf-capacity <K>      Maximum number of bodies per quadtree leaf [default: 1]
+  --threads <THREADS>      Threads for the direct sum, 0 for one per core [default: 0]
+  --seed <SEED>            Seed for the initial conditions [default: 0]
+  --ic <GENERATOR>         Initial-condition generator: disc, exponential_disc, plummer, hernquist, king,
+                           head_on or grazing [default: disc]
+  --help                   Print this message";
+
+struct Args {
+    config: SimulationConfig,
+    thetas: Vec<Float>,
+    fmm_orders: Vec<usize>,
+}
+
+impl Args {
+    fn parse() -> Result<Self, String> {
+        let mut args = Self {
+            config: SimulationConfig::default().n(20000),
+            thetas: vec![0.3, 0.5, 0.7, 1.0],
+            fmm_orders: vec![2, 4, 6],
+        };
+        let argv: Vec<String> = std::env::args().skip(1).collect();
+
+        // The config file is applied first so that other options can override it.
+        if let Some(i) = argv.iter().position(|arg| arg == "--config") {
+            let path = argv
+                .get(i + 1)
+                .ok_or_else(|| "missing value for '--config'".to_string())?;
+            args.config = SimulationConfig::load(path).map_err(|e| e.to_string())?;
+        }
+
+        let mut iter = argv.into_iter();
+        while let Some(flag) = iter.next() {
+            if flag == "--help" || flag == "-h" {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+
+            let value = iter
+                .next()
+                .ok_or_else(|| format!("missing value for '{flag}'"))?;
+
+            match flag.as_str() {
+                "--config" => {}
+                "--n" => args.config.n = parse_value(&flag, &value)?,
+                "--epsilon" => args.config.epsilon = parse_value(&flag, &value)?,
+                "--softening" => args.config.softening = value.parse()?,
+                "--thetas" => {
+                    args.thetas = value
+                        .split(',')
+                        .map(|theta| parse_value(&flag, theta.trim()))
+                        .collect::<Result<_, _>>()?
+                }
+                "--opening" => args.config.opening = value.parse()?,
+                "--alpha" => args.config.alpha = parse_value(&flag, &value)?,
+                "--box-guard" => args.config.box_guard = Some(parse_value(&flag, &value)?),
+                "--fmm-orders" => {
+                    args.fmm_orders = value
+                        .split(',')
+                        .filter(|order| !order.trim().is_empty())
+                        .map(|order| parse_value(&flag, order.trim()))
+                        .collect::<Result<_, _>>()?
+                }
+                "--multipole" => args.config.multipole = value.parse()?,
+                "--leaf-capacity" => args.config.leaf_capacity = parse_value(&flag, &value)?,
+                "--threads" => args.config.threads = parse_value(&flag, &value)?,
+                "--seed" => args.config.seed = parse_value(&flag, &value)?,
+                "--ic" => args.config.initial_conditions = value.parse()?,
+                _ => return Err(format!("unknown argument '{flag}'")),
+            }
+        }
+
+        // Each FMM order is checked as if it was the one in the config
+        args.config.validate().map_err(|e| e.to_string())?;
+        for &order in &args.fmm_orders {
+            let mut config = args.config.clone();
+            config.fmm.order = order;
+            config.validate().map_err(|e| e.to_string())?;
+        }
+
+        Ok(args)
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{value}' for '{flag}'"))
+}
+
+fn main() {
+    let args = match Args::parse() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error: {e}\n\n{USAGE}");
+            std::process::exit(1);
+        }
+    };
+    let config = &args.config;
+
+    let bodies = config.initial_conditions.generate(config);
+    let positions = bodies.iter().map(|body| body.pos).collect::<Vec<_>>();
+
+    let mut direct = DirectSum::new(config.epsilon);
+    direct.softening = config.softening;
+    direct.set_bodies(&bodies);
+    let exact = direct.accelerations(&positions, config.threads);
+
+    println!(
+        "{:>8} {:>12} {:>12} {:>12}",
+        "theta", "median", "99th", "max"
+    );
+    for &theta in &args.thetas {
+        let mut quadtree = Quadtree::new(theta, config.epsilon);
+        quadtree.softening = config.softening;
+        quadtree.opening = config.opening;
+        quadtree.alpha = config.alpha;
+        quadtree.box_guard = config.box_guard.unwrap_or(config.opening.guarded());
+        quadtree.multipole = config.multipole;
+        quadtree.leaf_capacity = config.leaf_capacity.max(1);
+        quadtree.build_morton_parallel(&bodies, config.threads);
+
+        let mut approx = bodies.clone();
+
+        // `Opening::Relative` needs a previous acceleration, so it gets a second pass seeded by
+        // the first one, like it would in a running simulation
+        let passes = if config.opening == Opening::Relative {
+            2
+        } else {
+            1
+        };
+        for _ in 0..passes {
+            parallel::for_each_chunk(&mut approx, config.threads, 1024, |chunk| {
+                for body in chunk {
+                    body.acc = quadtree.acc_of(body);
+                }
+            });
+        }
+        let approx = approx.iter().map(|body| body.acc).collect::<Vec<_>>();
+
+        let stats = ErrorStats::compare(&exact, &approx);
+        println!(
+            "{:>8} {:>12.3e} {:>12.3e} {:>12.3e}",
+            theta, stats.median, stats.p99, stats.max
+        );
+    }
+
+    if args.fmm_orders.is_empty() {
+        return;
+    }
+
+    println!();
+    println!(
+        "{:>8} {:>12} {:>12} {:>12}",
+        "order", "median", "99th", "max"
+    );
+    for &order in &args.fmm_orders {
+        let mut fmm = Fmm::new(
+            config.epsilon,
+            FmmConfig {
+                order,
+                ..config.fmm
+            },
+        );
+        fmm.quadtree.softening = config.softening;
+        let mut bodies = bodies.clone();
+        fmm.build(&mut bodies, config.threads);
+        fmm.attract(&mut bodies, &|_| true, config.threads);
+
+        let approx = bodies.iter().map(|body| body.acc).collect::<Vec<_>>();
+        let stats = ErrorStats::compare(&exact, &approx);
+        println!(
+            "{:>8} {:>12.3e} {:>12.3e} {:>12.3e}",
+            order, stats.median, stats.p99, stats.max
+        );
+    }
+}
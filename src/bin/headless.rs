@@ -0,0 +1,542 @@
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, ErrorKind},
+    mem,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use barnes_hut::{
+    export, float::widen, Catalog, Diagnostics, DiagnosticsWriter, Float, Format, Simulation,
+    Simulation3, SimulationConfig, Snapshot, TrajectoryWriter,
+};
+
+const USAGE: &str = "\
+Usage: headless [OPTIONS]
+
+Options:
+  --config <FILE>          Load parameters from a .toml or .ron config file, other options override it
+  --dimensions <D>         2, or 3 for an octree simulation without collisions [default: 2]
+  --n <N>                  Number of bodies [default: 100000]
+  --dt <DT>                Timestep [default: 0.05]
+  --solver <NAME>          Gravity solver: bh, fmm or direct [default: bh]
+  --theta <THETA>          Opening angle [default: 1.0]
+  --opening <NAME>         Opening criterion: geometric, bmax or relative [default: geometric]
+  --alpha <ALPHA>          Tolerance of the relative opening criterion [default: 0.005]
+  --box-guard <BOOL>       Always open nodes whose cell contains the body
+                           [default: true for bmax and relative, false for geometric]
+  --fmm-order <P>          Expansion order of the FMM solver [default: 4]
+  --epsilon <EPSILON>      Softening length [default: 1.0]
+  --softening <KERNEL>     Softening kernel: plummer, spline or none [default: plummer]
+  --adaptive-softening <MODE>
+                           Per-body softening lengths: fixed, radius or density [default: fixed]
+  --softening-scale <S>    Scale of the per-body softening lengths [default: 1.0]
+  --box-size <SIZE>        Enable periodic boundaries in a box of this size, centered on the origin
+  --ewald <BOOL>           Include all periodic images, not just the nearest [default: true]
+  --integrator <NAME>      Integrator: euler, leapfrog, verlet or block [default: euler]
+  --leaf-capacity <K>      Maximum number of bodies per quadtree leaf [default: 1]
+  --threads <THREADS>      Threads for the tree and forces, 0 for one per core [default: 0]
+  --seed <SEED>            Seed for the initial conditions [default: 0]
+  --ic <GENERATOR>         Initial-condition generator: disc, exponential_disc, plummer, hernquist, king,
+                           head_on, grazing, or sphere in 3D [default: disc]
+  --model-mass <MASS>      Total mass of the plummer, hernquist and king models [default: 100000]
+  --scale-radius <R>       Scale radius of the plummer, hernquist and king models [default: 300]
+  --w0 <W0>                Central potential of the king model, in units of σ² [default: 6]
+  --disc-mass <MASS>       Mass of the exponential disc [default: 100000]
+  --scale-length <H>       Scale length of the exponential disc [default: 300]
+  --bulge-mass <MASS>      Mass of the exponential disc's bulge, 0 for none [default: 20000]
+  --bulge-radius <R>       Scale radius of the bulge [default: 60]
+  --toomre-q <Q>           Toomre Q of the exponential disc [default: 1.5]
+  --steps <STEPS>          Number of steps to run, including those before a restart [default: 1000]
+  --time <TIME>            Simulated time to run, overrides --steps
+  --snapshot-every <K>     Write a snapshot every K steps, 0 to disable [default: 100]
+  --format <FORMAT>        Snapshot format: csv, npy or npz [default: csv]
+  --track <IDS>            Comma-separated bodies to write to a single trajectory file, by catalog id
+                           with --import and otherwise by index
+  --track-every <K>        Write the tracked bodies every K steps [default: 1]
+  --diagnostics-every <K>  Write diagnostics every K steps, 0 to disable [default: 10]
+  --checkpoint-every <K>   Write a binary checkpoint every K steps, 0 to disable [default: 0]
+  --restart <FILE>         Continue bit-for-bit from a checkpoint, ignoring the simulation options
+  --import <FILE>          Start from the bodies in a .csv or .json catalog instead of --ic, in 2D
+  --output <DIR>           Output directory [default: output]
+  --help                   Print this message";
+
+struct Args {
+    config: SimulationConfig,
+    steps: usize,
+    time: Option<Float>,
+    snapshot_every: usize,
+    format: Format,
+    track: Vec<u64>,
+    track_every: usize,
+    diagnostics_every: usize,
+    checkpoint_every: usize,
+    restart: Option<PathBuf>,
+    import: Option<PathBuf>,
+    output: PathBuf,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            config: SimulationConfig::default(),
+            steps: 1000,
+            time: None,
+            snapshot_every: 100,
+            format: Format::Csv,
+            track: Vec::new(),
+            track_every: 1,
+            diagnostics_every: 10,
+            checkpoint_every: 0,
+            restart: None,
+            import: None,
+            output: PathBuf::from("output"),
+        }
+    }
+}
+
+impl Args {
+    fn parse() -> Result<Self, String> {
+        let mut args = Self::default();
+        let argv: Vec<String> = std::env::args().skip(1).collect();
+
+        // The config file is applied first so that other options can override it.
+        if let Some(i) = argv.iter().position(|arg| arg == "--config") {
+            let path = argv
+                .get(i + 1)
+                .ok_or_else(|| "missing value for '--config'".to_string())?;
+            args.config = SimulationConfig::load(path).map_err(|e| e.to_string())?;
+        }
+
+        let mut iter = argv.into_iter();
+        while let Some(flag) = iter.next() {
+            if flag == "--help" || flag == "-h" {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+
+            let value = iter
+                .next()
+                .ok_or_else(|| format!("missing value for '{flag}'"))?;
+
+            match flag.as_str() {
+                "--config" => {}
+                "--dimensions" => {
+                    args.config.dimensions = parse_value(&flag, &value)?;
+                    // Collisions are on by default but only supported in 2D
+                    args.config.collisions.enabled &= args.config.dimensions == 2;
+                }
+                "--n" => args.config.n = parse_value(&flag, &value)?,
+                "--dt" => args.config.dt = parse_value(&flag, &value)?,
+                "--solver" => args.config.solver = value.parse()?,
+                "--theta" => args.config.theta = parse_value(&flag, &value)?,
+                "--opening" => args.config.opening = value.parse()?,
+                "--alpha" => args.config.alpha = parse_value(&flag, &value)?,
+                "--box-guard" => args.config.box_guard = Some(parse_value(&flag, &value)?),
+                "--fmm-order" => args.config.fmm.order = parse_value(&flag, &value)?,
+                "--epsilon" => args.config.epsilon = parse_value(&flag, &value)?,
+                "--softening" => args.config.softening = value.parse()?,
+                "--adaptive-softening" => args.config.adaptive_softening = value.parse()?,
+                "--softening-scale" => args.config.softening_scale = parse_value(&flag, &value)?,
+                "--box-size" => {
+                    args.config.periodic.enabled = true;
+                    args.config.periodic.size = parse_value(&flag, &value)?;
+                }
+                "--ewald" => args.config.periodic.ewald = parse_value(&flag, &value)?,
+                "--integrator" => args.config.integrator = value.parse()?,
+                "--leaf-capacity" => args.config.leaf_capacity = parse_value(&flag, &value)?,
+                "--threads" => args.config.threads = parse_value(&flag, &value)?,
+                "--seed" => args.config.seed = parse_value(&flag, &value)?,
+                "--ic" => args.config.initial_conditions = value.parse()?,
+                "--model-mass" => args.config.model.mass = parse_value(&flag, &value)?,
+                "--scale-radius" => args.config.model.scale_radius = parse_value(&flag, &value)?,
+                "--w0" => args.config.model.w0 = parse_value(&flag, &value)?,
+                "--disc-mass" => args.config.disc.mass = parse_value(&flag, &value)?,
+                "--scale-length" => args.config.disc.scale_length = parse_value(&flag, &value)?,
+                "--bulge-mass" => args.config.disc.bulge_mass = parse_value(&flag, &value)?,
+                "--bulge-radius" => args.config.disc.bulge_radius = parse_value(&flag, &value)?,
+                "--toomre-q" => args.config.disc.toomre_q = parse_value(&flag, &value)?,
+                "--steps" => args.steps = parse_value(&flag, &value)?,
+                "--time" => args.time = Some(parse_value(&flag, &value)?),
+                "--snapshot-every" => args.snapshot_every = parse_value(&flag, &value)?,
+                "--format" => args.format = value.parse()?,
+                "--track" => {
+                    args.track = value
+                        .split(',')
+                        .filter(|id| !id.trim().is_empty())
+                        .map(|id| parse_value(&flag, id.trim()))
+                        .collect::<Result<_, _>>()?
+                }
+                "--track-every" => args.track_every = parse_value(&flag, &value)?,
+                "--diagnostics-every" => args.diagnostics_every = parse_value(&flag, &value)?,
+                "--checkpoint-every" => args.checkpoint_every = parse_value(&flag, &value)?,
+                "--restart" => args.restart = Some(PathBuf::from(value)),
+                "--import" => args.import = Some(PathBuf::from(value)),
+                "--output" => args.output = PathBuf::from(value),
+                _ => return Err(format!("unknown argument '{flag}'")),
+            }
+        }
+
+        if args.config.dt <= 0.0 {
+            return Err("'--dt' must be positive".to_string());
+        }
+
+        let model = args.config.model;
+        if model.mass <= 0.0 || model.scale_radius <= 0.0 || model.w0 <= 0.0 {
+            return Err("'--model-mass', '--scale-radius' and '--w0' must be positive".to_string());
+        }
+
+        let disc = args.config.disc;
+        if disc.mass <= 0.0 || disc.scale_length <= 0.0 || disc.bulge_radius <= 0.0 {
+            return Err(
+                "'--disc-mass', '--scale-length' and '--bulge-radius' must be positive".to_string(),
+            );
+        }
+        if disc.bulge_mass < 0.0 || disc.toomre_q < 0.0 {
+            return Err("'--bulge-mass' and '--toomre-q' must not be negative".to_string());
+        }
+
+        let dimensions = args.config.dimensions;
+        if (args.checkpoint_every != 0 || args.restart.is_some()) && dimensions != 2 {
+            return Err("checkpoints are only supported in 2D".to_string());
+        }
+
+        if !args.track.is_empty() {
+            if args.track_every == 0 {
+                return Err("'--track-every' must be positive".to_string());
+            }
+            if args.config.reorder_bodies {
+                return Err("bodies can't be tracked when they are reordered".to_string());
+            }
+        }
+
+        if args.import.is_some() {
+            if dimensions != 2 {
+                return Err("catalogs can only be imported in 2D".to_string());
+            }
+            if args.restart.is_some() {
+                return Err("'--import' and '--restart' can't be combined".to_string());
+            }
+        }
+
+        args.config.validate().map_err(|err| err.to_string())?;
+
+        Ok(args)
+    }
+
+    // Frame to run until
+    fn steps(&self, dt: Float) -> usize {
+        match self.time {
+            Some(time) => (time / dt).ceil() as usize,
+            None => self.steps,
+        }
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{value}' for '{flag}'"))
+}
+
+// What `run` needs from `Simulation` and `Simulation3`
+trait Run {
+    fn frame(&self) -> usize;
+
+    fn dt(&self) -> Float;
+
+    fn len(&self) -> usize;
+
+    fn step(&mut self);
+
+    fn diagnostics(&self) -> Diagnostics;
+
+    fn write_snapshot(&self, path: &Path, format: Format) -> std::io::Result<()>;
+
+    fn write_trajectory(
+        &self,
+        trajectory: &mut TrajectoryWriter<BufWriter<File>>,
+        tracked: &[(u64, usize)],
+    ) -> std::io::Result<()>;
+
+    // `ids` are stored so that a restart can still track imported bodies by catalog id
+    fn write_checkpoint(&self, path: &Path, ids: Option<&[u64]>) -> Result<(), String>;
+}
+
+impl Run for Simulation {
+    fn frame(&self) -> usize {
+        self.frame
+    }
+
+    fn dt(&self) -> Float {
+        self.dt
+    }
+
+    fn len(&self) -> usize {
+        self.bodies.len()
+    }
+
+    fn step(&mut self) {
+        Simulation::step(self);
+    }
+
+    fn diagnostics(&self) -> Diagnostics {
+        Simulation::diagnostics(self)
+    }
+
+    fn write_snapshot(&self, path: &Path, format: Format) -> std::io::Result<()> {
+        export::write_state(&self.bodies, format, BufWriter::new(File::create(path)?))
+    }
+
+    fn write_trajectory(
+        &self,
+        trajectory: &mut TrajectoryWriter<BufWriter<File>>,
+        tracked: &[(u64, usize)],
+    ) -> std::io::Result<()> {
+        let time = self.frame as f64 * widen(self.dt);
+        trajectory.write(self.frame, time, &self.bodies, tracked)
+    }
+
+    fn write_checkpoint(&self, path: &Path, ids: Option<&[u64]>) -> Result<(), String> {
+        let mut snapshot = self.snapshot();
+        snapshot.ids = ids.unwrap_or_default().to_vec();
+        let file = File::create(path).map_err(|e| e.to_string())?;
+        snapshot
+            .write(BufWriter::new(file))
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl Run for Simulation3 {
+    fn frame(&self) -> usize {
+        self.frame
+    }
+
+    fn dt(&self) -> Float {
+        self.dt
+    }
+
+    fn len(&self) -> usize {
+        self.bodies.len()
+    }
+
+    fn step(&mut self) {
+        Simulation3::step(self);
+    }
+
+    fn diagnostics(&self) -> Diagnostics {
+        Simulation3::diagnostics(self)
+    }
+
+    fn write_snapshot(&self, path: &Path, format: Format) -> std::io::Result<()> {
+        export::write_state(&self.bodies, format, BufWriter::new(File::create(path)?))
+    }
+
+    fn write_trajectory(
+        &self,
+        trajectory: &mut TrajectoryWriter<BufWriter<File>>,
+        tracked: &[(u64, usize)],
+    ) -> std::io::Result<()> {
+        let time = self.frame as f64 * widen(self.dt);
+        trajectory.write(self.frame, time, &self.bodies, tracked)
+    }
+
+    fn write_checkpoint(&self, _path: &Path, _ids: Option<&[u64]>) -> Result<(), String> {
+        Err("checkpoints are only supported in 2D".to_string())
+    }
+}
+
+fn run(args: &Args) -> Result<(), String> {
+    fs::create_dir_all(&args.output).map_err(|e| e.to_string())?;
+
+    if let Some(path) = &args.restart {
+        let file = File::open(path).map_err(|e| e.to_string())?;
+        let mut snapshot = Snapshot::read(BufReader::new(file)).map_err(|e| e.to_string())?;
+        if !args.track.is_empty() && snapshot.config.reorder_bodies {
+            return Err("bodies can't be tracked when they are reordered".to_string());
+        }
+        let config = snapshot.config.to_toml();
+        fs::write(args.output.join("config.toml"), config).map_err(|e| e.to_string())?;
+
+        let ids = mem::take(&mut snapshot.ids);
+        let ids = (!ids.is_empty()).then_some(&ids[..]);
+        let simulation = Simulation::from_snapshot(snapshot).map_err(|e| e.to_string())?;
+        return run_simulation(args, simulation, ids);
+    }
+
+    fs::write(args.output.join("config.toml"), args.config.to_toml()).map_err(|e| e.to_string())?;
+
+    if let Some(path) = &args.import {
+        let catalog = Catalog::load(path).map_err(|e| e.to_string())?;
+        let simulation = args
+            .config
+            .build_with(catalog.bodies)
+            .map_err(|e| e.to_string())?;
+        return run_simulation(args, simulation, Some(&catalog.ids));
+    }
+
+    if args.config.dimensions == 3 {
+        let simulation = args.config.build3().map_err(|e| e.to_string())?;
+        run_simulation(args, simulation, None)
+    } else {
+        let simulation = args.config.build().map_err(|e| e.to_string())?;
+        run_simulation(args, simulation, None)
+    }
+}
+
+// `ids` are the catalog ids of the bodies, if they were imported
+fn run_simulation(
+    args: &Args,
+    mut simulation: impl Run,
+    ids: Option<&[u64]>,
+) -> Result<(), String> {
+    // Diagnostics start with the initial state, from which the energy drift is measured. After a
+    // restart into the same directory, the diagnostics up to the checkpoint are kept instead.
+    let diagnostics_path = args.output.join("diagnostics.csv");
+    let existing = read_existing(args, &diagnostics_path)?;
+    let file = BufWriter::new(File::create(&diagnostics_path).map_err(|e| e.to_string())?);
+    let mut diagnostics = match existing {
+        Some(existing) => {
+            let existing = String::from_utf8_lossy(&existing);
+            DiagnosticsWriter::resume(file, &existing, simulation.frame())
+        }
+        None => DiagnosticsWriter::new(file).and_then(|mut writer| {
+            if args.diagnostics_every != 0 {
+                writer.write(&simulation.diagnostics())?;
+            }
+            Ok(writer)
+        }),
+    }
+    .map_err(|e| format!("{}: {e}", diagnostics_path.display()))?;
+
+    let tracked = args
+        .track
+        .iter()
+        .map(|&id| {
+            let index = match ids {
+                Some(ids) => ids.iter().position(|&other| other == id),
+                None => usize::try_from(id).ok().filter(|&i| i < simulation.len()),
+            };
+            index
+                .map(|index| (id, index))
+                .ok_or_else(|| format!("no body with id {id} to track"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Trajectories can't go in an .npz, so they use .npy instead. Like the diagnostics, they are
+    // continued after a restart, which already has the rows up to the checkpoint.
+    let mut trajectory = None;
+    if !tracked.is_empty() {
+        let format = match args.format {
+            Format::Csv => Format::Csv,
+            Format::Npy | Format::Npz => Format::Npy,
+        };
+        let path = args
+            .output
+            .join(format!("trajectory.{}", format.extension()));
+        let existing = read_existing(args, &path)?;
+        let file = BufWriter::new(File::create(&path).map_err(|e| e.to_string())?);
+        let (frame, dimensions) = (simulation.frame(), args.config.dimensions);
+
+        let writer = match existing {
+            Some(existing) => TrajectoryWriter::resume(file, format, dimensions, &existing, frame),
+            None => TrajectoryWriter::new(file, format, dimensions).and_then(|mut writer| {
+                simulation.write_trajectory(&mut writer, &tracked)?;
+                Ok(writer)
+            }),
+        };
+        trajectory = Some(writer.map_err(|e| format!("{}: {e}", path.display()))?);
+    }
+
+    let start = Instant::now();
+    let first = simulation.frame();
+    let steps = args.steps(simulation.dt());
+    while simulation.frame() < steps {
+        if args.snapshot_every != 0 && simulation.frame().is_multiple_of(args.snapshot_every) {
+            let path = args.output.join(format!(
+                "snapshot_{:06}.{}",
+                simulation.frame(),
+                args.format.extension()
+            ));
+            simulation
+                .write_snapshot(&path, args.format)
+                .map_err(|e| e.to_string())?;
+        }
+
+        simulation.step();
+
+        if let Some(trajectory) = &mut trajectory {
+            if simulation.frame().is_multiple_of(args.track_every) {
+                simulation
+                    .write_trajectory(trajectory, &tracked)
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+
+        if args.diagnostics_every != 0 && simulation.frame().is_multiple_of(args.diagnostics_every)
+        {
+            diagnostics
+                .write(&simulation.diagnostics())
+                .map_err(|e| e.to_string())?;
+        }
+
+        if args.checkpoint_every != 0 && simulation.frame().is_multiple_of(args.checkpoint_every) {
+            let path = args
+                .output
+                .join(format!("checkpoint_{:06}.bin", simulation.frame()));
+            simulation.write_checkpoint(&path, ids)?;
+        }
+    }
+
+    println!(
+        "ran {} steps with {} bodies in {:.2?}",
+        simulation.frame() - first,
+        simulation.len(),
+        start.elapsed()
+    );
+
+    if args.snapshot_every != 0 {
+        let path = args.output.join(format!(
+            "snapshot_{:06}.{}",
+            simulation.frame(),
+            args.format.extension()
+        ));
+        simulation
+            .write_snapshot(&path, args.format)
+            .map_err(|e| e.to_string())?;
+    }
+
+    if let Some(trajectory) = trajectory {
+        trajectory.finish().map_err(|e| e.to_string())?;
+    }
+
+    diagnostics.flush().map_err(|e| e.to_string())
+}
+
+// Contents of an output file written before the checkpoint that the run was restarted from, if
+// there is one
+fn read_existing(args: &Args, path: &Path) -> Result<Option<Vec<u8>>, String> {
+    if args.restart.is_none() {
+        return Ok(None);
+    }
+    match fs::read(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("{}: {e}", path.display())),
+    }
+}
+
+fn main() {
+    let args = match Args::parse() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error: {e}\n\n{USAGE}");
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(e) = run(&args) {
+        eprintln!("error: {e}");
+        std::process::exit(1);
+    }
+}
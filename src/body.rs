@@ -1,27 +1,53 @@
-use ultraviolet::Vec2;
+use crate::{
+    float::{Float, Vec2, Vec3},
+    periodic::Periodic,
+    vector::Vector,
+};
 
 #[derive(Clone, Copy)]
-pub struct Body {
-    pub pos: Vec2,
-    pub vel: Vec2,
-    pub acc: Vec2,
-    pub mass: f32,
-    pub radius: f32,
+pub struct Body<V = Vec2> {
+    pub pos: V,
+    pub vel: V,
+    pub acc: V,
+    pub mass: Float,
+    pub radius: Float,
+    // Timestep level for block timestepping, the body's timestep is `dt / 2^level`
+    pub level: u32,
+    // Per-body softening length, see `AdaptiveSoftening`
+    pub softening: Float,
 }
 
-impl Body {
-    pub fn new(pos: Vec2, vel: Vec2, mass: f32, radius: f32) -> Self {
+pub type Body3 = Body<Vec3>;
+
+impl<V: Vector> Body<V> {
+    pub fn new(pos: V, vel: V, mass: Float, radius: Float) -> Self {
         Self {
             pos,
             vel,
-            acc: Vec2::zero(),
+            acc: V::zero(),
             mass,
             radius,
+            level: 0,
+            softening: 0.0,
         }
     }
 
-    pub fn update(&mut self, dt: f32) {
+    pub fn update(&mut self, dt: Float) {
+        self.kick(dt);
+        self.drift(dt);
+    }
+
+    pub fn kick(&mut self, dt: Float) {
         self.vel += self.acc * dt;
+    }
+
+    pub fn drift(&mut self, dt: Float) {
         self.pos += self.vel * dt;
     }
 }
+
+impl Body {
+    pub fn wrap(&mut self, periodic: &Periodic) {
+        self.pos = periodic.wrap(self.pos);
+    }
+}
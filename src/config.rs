@@ -0,0 +1,554 @@
+use std::{fmt, fs, path::Path, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    body::{Body, Body3},
+    float::{Float, Vec3},
+    fmm::{FmmConfig, MAX_ORDER},
+    integrator::{IntegratorKind, TimestepConfig, MAX_LEVEL},
+    kernel::{AdaptiveSoftening, Softening},
+    models,
+    periodic::Periodic,
+    quadtree::{Construction, Multipole, Opening},
+    scenario::Scenario,
+    simulation::Simulation,
+    simulation3::Simulation3,
+    solver::SolverKind,
+    utils,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InitialConditions {
+    UniformDisc,
+    // Only available with `dimensions = 3`
+    UniformSphere,
+    // Spherical equilibrium models set up by `ModelConfig`, flattened into the plane in 2D
+    Plummer,
+    Hernquist,
+    King,
+    // Set up by `DiscConfig`
+    ExponentialDisc,
+    // Two discs from `Scenario::head_on` and `Scenario::grazing`
+    HeadOn,
+    Grazing,
+}
+
+impl InitialConditions {
+    pub fn supports(&self, dimensions: usize) -> bool {
+        match self {
+            Self::UniformSphere => dimensions == 3,
+            _ => true,
+        }
+    }
+
+    pub fn generate(&self, config: &SimulationConfig) -> Vec<Body> {
+        let (n, seed, model) = (config.n, config.seed, &config.model);
+        match self {
+            Self::UniformDisc => utils::uniform_disc(n, seed),
+            Self::ExponentialDisc => utils::exponential_disc(n, &config.disc, seed),
+            Self::UniformSphere => panic!("'uniform_sphere' initial conditions need 3 dimensions"),
+            Self::Plummer => models::plummer(n, model.mass, model.scale_radius, seed),
+            Self::Hernquist => models::hernquist(n, model.mass, model.scale_radius, seed),
+            Self::King => models::king(n, model.mass, model.scale_radius, model.w0, seed),
+            Self::HeadOn => Scenario::head_on(n, seed).build(),
+            Self::Grazing => Scenario::grazing(n, seed).build(),
+        }
+    }
+
+    // 2D generators are placed in the `z = 0` plane
+    pub fn generate3(&self, config: &SimulationConfig) -> Vec<Body3> {
+        let (n, seed, model) = (config.n, config.seed, &config.model);
+        match self {
+            Self::UniformDisc | Self::ExponentialDisc | Self::HeadOn | Self::Grazing => self
+                .generate(config)
+                .into_iter()
+                .map(|body| {
+                    let pos = Vec3::new(body.pos.x, body.pos.y, 0.0);
+                    let vel = Vec3::new(body.vel.x, body.vel.y, 0.0);
+                    Body3::new(pos, vel, body.mass, body.radius)
+                })
+                .collect(),
+            Self::UniformSphere => utils::uniform_sphere(n, seed),
+            Self::Plummer => models::plummer3(n, model.mass, model.scale_radius, seed),
+            Self::Hernquist => models::hernquist3(n, model.mass, model.scale_radius, seed),
+            Self::King => models::king3(n, model.mass, model.scale_radius, model.w0, seed),
+        }
+    }
+}
+
+impl FromStr for InitialConditions {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disc" | "uniform_disc" => Ok(Self::UniformDisc),
+            "exponential_disc" => Ok(Self::ExponentialDisc),
+            "sphere" | "uniform_sphere" => Ok(Self::UniformSphere),
+            "plummer" => Ok(Self::Plummer),
+            "hernquist" => Ok(Self::Hernquist),
+            "king" => Ok(Self::King),
+            "head_on" => Ok(Self::HeadOn),
+            "grazing" => Ok(Self::Grazing),
+            _ => Err(format!("unknown initial-condition generator '{s}'")),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CollisionConfig {
+    pub enabled: bool,
+    pub restitution: Float,
+}
+
+impl Default for CollisionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            restitution: 0.5,
+        }
+    }
+}
+
+// Parameters of the `plummer`, `hernquist` and `king` initial conditions
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModelConfig {
+    // Total mass, shared equally between the bodies
+    pub mass: Float,
+    pub scale_radius: Float,
+    // Central potential of the King model in units of the velocity dispersion squared
+    pub w0: Float,
+}
+
+impl Default for ModelConfig {
+    fn default() -> Self {
+        Self {
+            mass: 100000.0,
+            scale_radius: 300.0,
+            w0: 6.0,
+        }
+    }
+}
+
+// Parameters of the `exponential_disc` initial conditions
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DiscConfig {
+    pub mass: Float,
+    // Scale length `h` of the surface density `Σ ∝ exp(-R / h)`
+    pub scale_length: Float,
+    // Hernquist bulge at the center, none if the mass is zero
+    pub bulge_mass: Float,
+    pub bulge_radius: Float,
+    // Toomre stability parameter of the disc, which sets its radial velocity dispersion
+    pub toomre_q: Float,
+}
+
+impl Default for DiscConfig {
+    fn default() -> Self {
+        Self {
+            mass: 100000.0,
+            scale_length: 300.0,
+            bulge_mass: 20000.0,
+            bulge_radius: 60.0,
+            toomre_q: 1.5,
+        }
+    }
+}
+
+// Only supported by `Simulation` with the Barnes-Hut and direct solvers
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PeriodicConfig {
+    pub enabled: bool,
+    // Side of the box, which is centered on the origin
+    pub size: Float,
+    // Adds the forces from all periodic images rather than only the nearest one
+    pub ewald: bool,
+}
+
+impl Default for PeriodicConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            size: 4000.0,
+            ewald: true,
+        }
+    }
+}
+
+impl PeriodicConfig {
+    pub fn build(&self) -> Option<Periodic> {
+        self.enabled.then(|| Periodic::new(self.size, self.ewald))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SimulationConfig {
+    // 2 for `Simulation`, 3 for `Simulation3`
+    pub dimensions: usize,
+    pub dt: Float,
+    pub solver: SolverKind,
+    pub theta: Float,
+    pub opening: Opening,
+    // Tolerance of `Opening::Relative`
+    pub alpha: Float,
+    // `Quadtree::box_guard`, or `Opening::guarded` when unset
+    pub box_guard: Option<bool>,
+    // Only used by `SolverKind::Fmm`
+    pub fmm: FmmConfig,
+    pub epsilon: Float,
+    pub softening: Softening,
+    pub adaptive_softening: AdaptiveSoftening,
+    // Multiplies the per-body lengths of `adaptive_softening`
+    pub softening_scale: Float,
+    pub integrator: IntegratorKind,
+    // Only used by `IntegratorKind::BlockLeapfrog`
+    pub timesteps: TimestepConfig,
+    pub multipole: Multipole,
+    pub leaf_capacity: usize,
+    pub construction: Construction,
+    pub reorder_bodies: bool,
+    // Threads used for tree construction and force evaluation, 0 for one per core
+    pub threads: usize,
+    pub n: usize,
+    pub seed: u64,
+    pub initial_conditions: InitialConditions,
+    pub model: ModelConfig,
+    pub disc: DiscConfig,
+    pub collisions: CollisionConfig,
+    pub periodic: PeriodicConfig,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            dimensions: 2,
+            dt: 0.05,
+            solver: SolverKind::BarnesHut,
+            theta: 1.0,
+            opening: Opening::Geometric,
+            alpha: 0.005,
+            box_guard: None,
+            fmm: FmmConfig::default(),
+            epsilon: 1.0,
+            softening: Softening::Plummer,
+            adaptive_softening: AdaptiveSoftening::Fixed,
+            softening_scale: 1.0,
+            integrator: IntegratorKind::SemiImplicitEuler,
+            timesteps: TimestepConfig::default(),
+            multipole: Multipole::Monopole,
+            leaf_capacity: 1,
+            construction: Construction::Insert,
+            reorder_bodies: false,
+            threads: 0,
+            n: 100000,
+            seed: 0,
+            initial_conditions: InitialConditions::UniformDisc,
+            model: ModelConfig::default(),
+            disc: DiscConfig::default(),
+            collisions: CollisionConfig::default(),
+            periodic: PeriodicConfig::default(),
+        }
+    }
+}
+
+impl SimulationConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn dimensions(mut self, dimensions: usize) -> Self {
+        self.dimensions = dimensions;
+        self
+    }
+
+    pub fn dt(mut self, dt: Float) -> Self {
+        self.dt = dt;
+        self
+    }
+
+    pub fn solver(mut self, solver: SolverKind) -> Self {
+        self.solver = solver;
+        self
+    }
+
+    pub fn theta(mut self, theta: Float) -> Self {
+        self.theta = theta;
+        self
+    }
+
+    pub fn opening(mut self, opening: Opening) -> Self {
+        self.opening = opening;
+        self
+    }
+
+    pub fn alpha(mut self, alpha: Float) -> Self {
+        self.alpha = alpha;
+        self
+    }
+
+    pub fn box_guard(mut self, box_guard: bool) -> Self {
+        self.box_guard = Some(box_guard);
+        self
+    }
+
+    pub fn fmm(mut self, fmm: FmmConfig) -> Self {
+        self.fmm = fmm;
+        self
+    }
+
+    pub fn epsilon(mut self, epsilon: Float) -> Self {
+        self.epsilon = epsilon;
+        self
+    }
+
+    pub fn softening(mut self, softening: Softening) -> Self {
+        self.softening = softening;
+        self
+    }
+
+    pub fn adaptive_softening(mut self, adaptive_softening: AdaptiveSoftening) -> Self {
+        self.adaptive_softening = adaptive_softening;
+        self
+    }
+
+    pub fn softening_scale(mut self, softening_scale: Float) -> Self {
+        self.softening_scale = softening_scale;
+        self
+    }
+
+    pub fn integrator(mut self, integrator: IntegratorKind) -> Self {
+        self.integrator = integrator;
+        self
+    }
+
+    pub fn timesteps(mut self, timesteps: TimestepConfig) -> Self {
+        self.timesteps = timesteps;
+        self
+    }
+
+    pub fn multipole(mut self, multipole: Multipole) -> Self {
+        self.multipole = multipole;
+        self
+    }
+
+    pub fn leaf_capacity(mut self, leaf_capacity: usize) -> Self {
+        self.leaf_capacity = leaf_capacity;
+        self
+    }
+
+    pub fn construction(mut self, construction: Construction) -> Self {
+        self.construction = construction;
+        self
+    }
+
+    pub fn reorder_bodies(mut self, reorder_bodies: bool) -> Self {
+        self.reorder_bodies = reorder_bodies;
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    pub fn n(mut self, n: usize) -> Self {
+        self.n = n;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn initial_conditions(mut self, initial_conditions: InitialConditions) -> Self {
+        self.initial_conditions = initial_conditions;
+        self
+    }
+
+    pub fn model(mut self, model: ModelConfig) -> Self {
+        self.model = model;
+        self
+    }
+
+    pub fn disc(mut self, disc: DiscConfig) -> Self {
+        self.disc = disc;
+        self
+    }
+
+    pub fn collisions(mut self, collisions: CollisionConfig) -> Self {
+        self.collisions = collisions;
+        self
+    }
+
+    pub fn periodic(mut self, periodic: PeriodicConfig) -> Self {
+        self.periodic = periodic;
+        self
+    }
+
+    /// Generates the initial conditions and builds a simulation from them, if the config is valid.
+    pub fn build(&self) -> Result<Simulation, ConfigError> {
+        self.validate_for(2)?;
+        let bodies = self.initial_conditions.generate(self);
+        self.build_with(bodies)
+    }
+
+    /// Builds a simulation from the given bodies, ignoring `n`, `seed`, `initial_conditions`, `model` and `disc`.
+    pub fn build_with(&self, bodies: Vec<Body>) -> Result<Simulation, ConfigError> {
+        Simulation::from_config(bodies, self)
+    }
+
+    /// Generates the initial conditions in 3D and builds a `Simulation3` from them, if the config is valid.
+    pub fn build3(&self) -> Result<Simulation3, ConfigError> {
+        self.validate_for(3)?;
+        let bodies = self.initial_conditions.generate3(self);
+        Simulation3::from_config(bodies, self)
+    }
+
+    // `build` makes 2D simulations and `build3` 3D ones, each only from a config with those dimensions
+    pub(crate) fn validate_for(&self, dimensions: usize) -> Result<(), ConfigError> {
+        self.validate()?;
+        if self.dimensions != dimensions {
+            let build = if self.dimensions == 3 {
+                "build3"
+            } else {
+                "build"
+            };
+            return Err(ConfigError::Invalid(format!(
+                "'dimensions' is {}, so the simulation must be made with `{build}`",
+                self.dimensions
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks the parameters that are valid for their types but can't be simulated.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.dimensions != 2 && self.dimensions != 3 {
+            return Err(ConfigError::Invalid(
+                "'dimensions' must be 2 or 3".to_string(),
+            ));
+        }
+        if !self.initial_conditions.supports(self.dimensions) {
+            return Err(ConfigError::Invalid(format!(
+                "'initial_conditions' can't be {:?} in {}D",
+                self.initial_conditions, self.dimensions
+            )));
+        }
+        // `Simulation3` only has Barnes-Hut on an octree with monopoles and the geometric criterion
+        if self.dimensions == 3 {
+            let changed = [
+                ("solver", self.solver != SolverKind::BarnesHut),
+                ("opening", self.opening != Opening::Geometric),
+                ("box_guard", self.box_guard == Some(true)),
+                ("multipole", self.multipole != Multipole::Monopole),
+                (
+                    "adaptive_softening",
+                    self.adaptive_softening != AdaptiveSoftening::Fixed,
+                ),
+            ];
+            if let Some((name, _)) = changed.iter().find(|(_, changed)| *changed) {
+                return Err(ConfigError::Invalid(format!(
+                    "'{name}' can only be changed from its default in 2D"
+                )));
+            }
+            if self.collisions.enabled {
+                return Err(ConfigError::Invalid(
+                    "collisions are only supported in 2D, set 'collisions.enabled' to false"
+                        .to_string(),
+                ));
+            }
+        }
+        if self.timesteps.max_level > MAX_LEVEL {
+            return Err(ConfigError::Invalid(format!(
+                "'timesteps.max_level' can be at most {MAX_LEVEL}"
+            )));
+        }
+        if self.fmm.order > MAX_ORDER {
+            return Err(ConfigError::Invalid(format!(
+                "'fmm.order' can be at most {MAX_ORDER}"
+            )));
+        }
+        if self.periodic.enabled {
+            if self.periodic.size <= 0.0 {
+                return Err(ConfigError::Invalid(
+                    "'periodic.size' must be positive".to_string(),
+                ));
+            }
+            if self.dimensions != 2 || self.solver == SolverKind::Fmm {
+                return Err(ConfigError::Invalid(
+                    "periodic boundaries need 2 dimensions and the Barnes-Hut or direct solver"
+                        .to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn from_toml(s: &str) -> Result<Self, ConfigError> {
+        let config: Self = toml::from_str(s).map_err(ConfigError::Toml)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_ron(s: &str) -> Result<Self, ConfigError> {
+        let config: Self = ron::from_str(s).map_err(ConfigError::Ron)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).expect("config is always representable as TOML")
+    }
+
+    pub fn to_ron(&self) -> String {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .expect("config is always representable as RON")
+    }
+
+    /// Loads a config file, picking the format from the `.toml` or `.ron` extension.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path).map_err(ConfigError::Io)?;
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::from_toml(&contents),
+            Some("ron") => Self::from_ron(&contents),
+            _ => Err(ConfigError::UnknownFormat(path.display().to_string())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+    Ron(ron::error::SpannedError),
+    UnknownFormat(String),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "failed to read config: {e}"),
+            Self::Toml(e) => write!(f, "invalid TOML config: {e}"),
+            Self::Ron(e) => write!(f, "invalid RON config: {e}"),
+            Self::UnknownFormat(path) => {
+                write!(
+                    f,
+                    "unknown config format for '{path}', expected .toml or .ron"
+                )
+            }
+            Self::Invalid(reason) => write!(f, "invalid config: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
@@ -0,0 +1,169 @@
+use std::io::{self, Write};
+
+use ultraviolet::DVec3;
+
+use crate::{
+    body::Body,
+    float::{widen, Float},
+    simulation::Simulation,
+    simulation3::Simulation3,
+    vector::Vector,
+};
+
+// Conserved quantities of a simulation at one point in time, accumulated in double precision.
+// 2D simulations have zero `momentum.z` and only `angular_momentum.z`.
+#[derive(Clone, Copy, Debug)]
+pub struct Diagnostics {
+    pub frame: usize,
+    pub time: f64,
+    pub kinetic: f64,
+    pub potential: f64,
+    pub momentum: DVec3,
+    pub angular_momentum: DVec3,
+}
+
+impl Diagnostics {
+    // Takes the potential energy from the solver of the last force evaluation, which is built
+    // just for this before the first step
+    pub fn measure(simulation: &Simulation) -> Self {
+        let potentials = simulation.potentials();
+        let time = simulation.frame as f64 * widen(simulation.dt);
+        Self::from_bodies(simulation.frame, time, &simulation.bodies, &potentials)
+    }
+
+    // Same as `measure`, with the octree from the last force evaluation
+    pub fn measure3(simulation: &Simulation3) -> Self {
+        let potentials = simulation.potentials();
+        let time = simulation.frame as f64 * widen(simulation.dt);
+        Self::from_bodies(simulation.frame, time, &simulation.bodies, &potentials)
+    }
+
+    // `potentials` holds the potential at each body, excluding the body itself
+    pub fn from_bodies<V: Vector>(
+        frame: usize,
+        time: f64,
+        bodies: &[Body<V>],
+        potentials: &[Float],
+    ) -> Self {
+        let mut kinetic = 0.0;
+        let mut momentum = DVec3::zero();
+        let mut angular_momentum = DVec3::zero();
+
+        for body in bodies {
+            let mass = widen(body.mass);
+            let pos = body.pos.to_dvec3();
+            let vel = body.vel.to_dvec3();
+
+            kinetic += 0.5 * mass * vel.mag_sq();
+            momentum += vel * mass;
+            angular_momentum += pos.cross(vel) * mass;
+        }
+
+        // Every pair is counted from both sides
+        let potential = 0.5
+            * bodies
+                .iter()
+                .zip(potentials)
+                .map(|(body, &potential)| widen(body.mass) * widen(potential))
+                .sum::<f64>();
+
+        Self {
+            frame,
+            time,
+            kinetic,
+            potential,
+            momentum,
+            angular_momentum,
+        }
+    }
+
+    pub fn total_energy(&self) -> f64 {
+        self.kinetic + self.potential
+    }
+
+    // 2K / |W|, which is 1 for a system in virial equilibrium
+    pub fn virial_ratio(&self) -> f64 {
+        2.0 * self.kinetic / self.potential.abs()
+    }
+}
+
+// Writes diagnostics as CSV rows, including the drift of the total energy since the first row
+pub struct DiagnosticsWriter<W: Write> {
+    writer: W,
+    initial_energy: Option<f64>,
+}
+
+const HEADER: &str = "frame,time,kinetic,potential,total,energy_drift,momentum_x,momentum_y,momentum_z,angular_momentum_x,angular_momentum_y,angular_momentum_z,virial_ratio";
+
+impl<W: Write> DiagnosticsWriter<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writeln!(writer, "{HEADER}")?;
+
+        Ok(Self {
+            writer,
+            initial_energy: None,
+        })
+    }
+
+    // Continues `existing`, the CSV of an earlier run, after a restart from `frame`. Its rows up to
+    // `frame` are written again, later ones are dropped, and the energy drift stays relative to its
+    // first row.
+    pub fn resume(mut writer: W, existing: &str, frame: usize) -> io::Result<Self> {
+        let invalid = |reason| io::Error::new(io::ErrorKind::InvalidData, reason);
+
+        let mut lines = existing.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(invalid("existing diagnostics have a different header"));
+        }
+        writeln!(writer, "{HEADER}")?;
+
+        let mut initial_energy = None;
+        for line in lines.filter(|line| !line.is_empty()) {
+            let fields = line.split(',').collect::<Vec<_>>();
+            let row_frame = fields.first().and_then(|field| field.parse::<usize>().ok());
+            let total = fields.get(4).and_then(|field| field.parse::<f64>().ok());
+            let (Some(row_frame), Some(total)) = (row_frame, total) else {
+                return Err(invalid("existing diagnostics have a malformed row"));
+            };
+
+            if row_frame > frame {
+                break;
+            }
+            initial_energy.get_or_insert(total);
+            writeln!(writer, "{line}")?;
+        }
+
+        Ok(Self {
+            writer,
+            initial_energy,
+        })
+    }
+
+    pub fn write(&mut self, diagnostics: &Diagnostics) -> io::Result<()> {
+        let total = diagnostics.total_energy();
+        let initial = *self.initial_energy.get_or_insert(total);
+        let drift = (total - initial) / initial.abs();
+
+        writeln!(
+            self.writer,
+            "{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            diagnostics.frame,
+            diagnostics.time,
+            diagnostics.kinetic,
+            diagnostics.potential,
+            total,
+            drift,
+            diagnostics.momentum.x,
+            diagnostics.momentum.y,
+            diagnostics.momentum.z,
+            diagnostics.angular_momentum.x,
+            diagnostics.angular_momentum.y,
+            diagnostics.angular_momentum.z,
+            diagnostics.virial_ratio()
+        )
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,143 @@
+use crate::{
+    body::Body,
+    float::{widen, Float, Vec2},
+    kernel::Softening,
+    parallel,
+    periodic::Periodic,
+    quadtree::Point,
+};
+
+// Exact O(N²) summation over every body, with the same force law as `Quadtree`
+pub struct DirectSum {
+    pub epsilon: Float,
+    pub softening: Softening,
+    // Sums over the nearest image of every body, plus the Ewald correction if enabled
+    pub periodic: Option<Periodic>,
+    pub points: Vec<Point>,
+}
+
+impl DirectSum {
+    pub fn new(epsilon: Float) -> Self {
+        Self {
+            epsilon,
+            softening: Softening::Plummer,
+            periodic: None,
+            points: Vec::new(),
+        }
+    }
+
+    pub fn set_bodies(&mut self, bodies: &[Body]) {
+        self.points.clear();
+        self.points
+            .extend(bodies.iter().enumerate().map(|(index, body)| Point {
+                pos: body.pos,
+                mass: body.mass,
+                softening: body.softening,
+                index,
+            }));
+    }
+
+    pub fn acc(&self, pos: Vec2) -> Vec2 {
+        self.acc_with(pos, 0.0)
+    }
+
+    pub fn acc_of(&self, body: &Body) -> Vec2 {
+        self.acc_with(body.pos, body.softening)
+    }
+
+    fn acc_with(&self, pos: Vec2, softening: Float) -> Vec2 {
+        let epsilon = self.epsilon.max(softening);
+        let mut acc = Vec2::zero();
+        for p in &self.points {
+            let e = epsilon.max(p.softening);
+            match &self.periodic {
+                Some(periodic) => {
+                    let d = periodic.nearest(p.pos - pos);
+                    acc += self.softening.acc(d, d.mag_sq(), p.mass, e);
+                    acc += periodic.correction_acc(d, p.mass);
+                }
+                None => {
+                    let d = p.pos - pos;
+                    acc += self.softening.acc(d, d.mag_sq(), p.mass, e);
+                }
+            }
+        }
+        acc
+    }
+
+    pub fn potential(&self, pos: Vec2) -> Float {
+        self.potential_with(pos, 0.0)
+    }
+
+    pub fn potential_of(&self, body: &Body) -> Float {
+        self.potential_with(body.pos, body.softening)
+    }
+
+    fn potential_with(&self, pos: Vec2, softening: Float) -> Float {
+        let epsilon = self.epsilon.max(softening);
+        let mut potential = 0.0;
+        for p in &self.points {
+            let e = epsilon.max(p.softening);
+            match &self.periodic {
+                Some(periodic) => {
+                    let d = periodic.nearest(p.pos - pos);
+                    potential += self.softening.potential(d.mag_sq(), p.mass, e);
+                    potential += periodic.correction_potential(d, p.mass);
+                }
+                None => {
+                    potential += self.softening.potential((p.pos - pos).mag_sq(), p.mass, e);
+                }
+            }
+        }
+        potential
+    }
+
+    // Accelerations at every position, spread across `threads` (0 for one per core)
+    pub fn accelerations(&self, positions: &[Vec2], threads: usize) -> Vec<Vec2> {
+        let mut accs = positions.to_vec();
+        parallel::for_each_chunk(&mut accs, threads, 64, |chunk| {
+            for pos in chunk {
+                *pos = self.acc(*pos);
+            }
+        });
+        accs
+    }
+}
+
+// Distribution of per-body relative force errors `|a - a_exact| / |a_exact|`
+#[derive(Clone, Copy, Debug)]
+pub struct ErrorStats {
+    pub median: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl ErrorStats {
+    pub fn compare(exact: &[Vec2], approx: &[Vec2]) -> Self {
+        assert_eq!(exact.len(), approx.len());
+
+        let mut errors = exact
+            .iter()
+            .zip(approx)
+            .filter(|(exact, _)| exact.mag_sq() != 0.0)
+            .map(|(exact, approx)| widen((*approx - *exact).mag() / exact.mag()))
+            .collect::<Vec<_>>();
+
+        if errors.is_empty() {
+            return Self {
+                median: 0.0,
+                p99: 0.0,
+                max: 0.0,
+            };
+        }
+
+        errors.sort_by(f64::total_cmp);
+        let percentile = |p: f64| errors[((errors.len() - 1) as f64 * p).round() as usize];
+
+        Self {
+            median: percentile(0.5),
+            p99: percentile(0.99),
+            max: errors[errors.len() - 1],
+        }
+    }
+}
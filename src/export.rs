@@ -0,0 +1,627 @@
+use std::{
+    fmt,
+    io::{self, Seek, SeekFrom, Write},
+    mem,
+    str::FromStr,
+};
+
+use crate::{
+    body::Body,
+    float::{widen, Float},
+    vector::Vector,
+};
+
+// Bytes reserved for the header of a trajectory .npy file, whose shape is only known at the end
+const TRAJECTORY_HEADER: usize = 128;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    // One `(bodies, columns)` array with the same columns as the CSV
+    Npy,
+    // Uncompressed archive of `pos`, `vel` and `acc` arrays of shape `(bodies, dimensions)` and
+    // `mass` and `radius` arrays of shape `(bodies,)`
+    Npz,
+}
+
+impl Format {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Npy => "npy",
+            Self::Npz => "npz",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "npy" => Ok(Self::Npy),
+            "npz" => Ok(Self::Npz),
+            _ => Err(format!("unknown export format '{s}'")),
+        }
+    }
+}
+
+// Names of the columns written for each body with `dimensions` components
+pub fn columns(dimensions: usize) -> Vec<String> {
+    let axes = &["x", "y", "z"][..dimensions];
+    let mut columns = axes.iter().map(|axis| axis.to_string()).collect::<Vec<_>>();
+    for prefix in ["v", "a"] {
+        columns.extend(axes.iter().map(|axis| format!("{prefix}{axis}")));
+    }
+    columns.extend(["mass".to_string(), "radius".to_string()]);
+    columns
+}
+
+// Appends the columns of `body` to `row`
+fn push_row<V: Vector>(row: &mut Vec<Float>, body: &Body<V>) {
+    for v in [body.pos, body.vel, body.acc] {
+        row.extend_from_slice(&v.components()[..V::DIMENSIONS]);
+    }
+    row.extend([body.mass, body.radius]);
+}
+
+fn write_csv_row<T: fmt::Display>(writer: &mut impl Write, row: &[T]) -> io::Result<()> {
+    for (i, value) in row.iter().enumerate() {
+        if i != 0 {
+            write!(writer, ",")?;
+        }
+        write!(writer, "{value}")?;
+    }
+    writeln!(writer)
+}
+
+// Positions, velocities, accelerations, masses and radii of every body at one point in time
+pub fn write_state<V: Vector>(
+    bodies: &[Body<V>],
+    format: Format,
+    mut writer: impl Write,
+) -> io::Result<()> {
+    let columns = columns(V::DIMENSIONS);
+
+    match format {
+        Format::Csv => {
+            write_csv_row(&mut writer, &columns)?;
+            let mut row = Vec::with_capacity(columns.len());
+            for body in bodies {
+                row.clear();
+                push_row(&mut row, body);
+                write_csv_row(&mut writer, &row)?;
+            }
+        }
+        Format::Npy => {
+            let mut data = Vec::with_capacity(bodies.len() * columns.len());
+            for body in bodies {
+                push_row(&mut data, body);
+            }
+            write_npy(&mut writer, &[bodies.len(), columns.len()], &data)?;
+        }
+        Format::Npz => {
+            let vectors = |f: fn(&Body<V>) -> V| {
+                let data = bodies
+                    .iter()
+                    .flat_map(|body| f(body).components().into_iter().take(V::DIMENSIONS))
+                    .collect::<Vec<_>>();
+                npy(&[bodies.len(), V::DIMENSIONS], &data)
+            };
+            let scalars = |f: fn(&Body<V>) -> Float| {
+                let data = bodies.iter().map(f).collect::<Vec<_>>();
+                npy(&[bodies.len()], &data)
+            };
+
+            let arrays = [
+                ("pos", vectors(|body| body.pos)),
+                ("vel", vectors(|body| body.vel)),
+                ("acc", vectors(|body| body.acc)),
+                ("mass", scalars(|body| body.mass)),
+                ("radius", scalars(|body| body.radius)),
+            ];
+            write_zip(&mut writer, &arrays)?;
+        }
+    }
+
+    writer.flush()
+}
+
+// A single array in NumPy's .npy format, `data` being in row-major order
+pub fn write_npy(mut writer: impl Write, shape: &[usize], data: &[Float]) -> io::Result<()> {
+    writer.write_all(&npy(shape, data))
+}
+
+// Element types of the .npy arrays that get written
+trait Element: Copy {
+    const DESCR: &'static str;
+
+    fn extend_le(self, bytes: &mut Vec<u8>);
+}
+
+impl Element for f32 {
+    const DESCR: &'static str = "<f4";
+
+    fn extend_le(self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Element for f64 {
+    const DESCR: &'static str = "<f8";
+
+    fn extend_le(self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+fn npy<T: Element>(shape: &[usize], data: &[T]) -> Vec<u8> {
+    let mut bytes = npy_header::<T>(shape, 0);
+    bytes.reserve(mem::size_of_val(data));
+    for &value in data {
+        value.extend_le(&mut bytes);
+    }
+    bytes
+}
+
+// Version 1.0 header, padded with spaces to a multiple of 64 bytes and at least `len`
+fn npy_header<T: Element>(shape: &[usize], len: usize) -> Vec<u8> {
+    let shape = match shape {
+        [n] => format!("({n},)"),
+        _ => {
+            let dims = shape.iter().map(usize::to_string).collect::<Vec<_>>();
+            format!("({})", dims.join(", "))
+        }
+    };
+    let mut dict = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {shape}, }}",
+        T::DESCR
+    );
+
+    // Magic, version and header length come first, and the header ends with a newline
+    let total = (10 + dict.len() + 1).next_multiple_of(64).max(len);
+    dict.extend(std::iter::repeat_n(' ', total - 11 - dict.len()));
+    dict.push('\n');
+
+    let mut bytes = Vec::with_capacity(total);
+    bytes.extend_from_slice(b"\x93NUMPY\x01\x00");
+    bytes.extend_from_slice(&(dict.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(dict.as_bytes());
+    bytes
+}
+
+// Zip archive of `name.npy` files, stored without compression, as read by `numpy.load`
+fn write_zip(writer: &mut impl Write, files: &[(&str, Vec<u8>)]) -> io::Result<()> {
+    let too_large = || io::Error::new(io::ErrorKind::InvalidInput, "archive exceeds 4 GiB");
+    // 1980-01-01, the earliest date zip can represent
+    let (time, date) = (0u16, (1 << 5) | 1u16);
+
+    let mut central = Vec::new();
+    let mut offset = 0u32;
+    for (name, data) in files {
+        let name = format!("{name}.npy");
+        let crc = crc32(data);
+        let size = u32::try_from(data.len()).map_err(|_| too_large())?;
+
+        let mut local = Vec::with_capacity(30 + name.len());
+        local.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        local.extend_from_slice(&20u16.to_le_bytes());
+        local.extend_from_slice(&0u16.to_le_bytes());
+        local.extend_from_slice(&0u16.to_le_bytes());
+        local.extend_from_slice(&time.to_le_bytes());
+        local.extend_from_slice(&date.to_le_bytes());
+        local.extend_from_slice(&crc.to_le_bytes());
+        local.extend_from_slice(&size.to_le_bytes());
+        local.extend_from_slice(&size.to_le_bytes());
+        local.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        local.extend_from_slice(&0u16.to_le_bytes());
+        local.extend_from_slice(name.as_bytes());
+        writer.write_all(&local)?;
+        writer.write_all(data)?;
+
+        central.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        central.extend_from_slice(&20u16.to_le_bytes());
+        central.extend_from_slice(&local[4..30]);
+        // Comment length, disk number, internal and external attributes
+        central.extend_from_slice(&[0; 10]);
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+
+        offset = u32::try_from(local.len() + data.len())
+            .ok()
+            .and_then(|len| offset.checked_add(len))
+            .ok_or_else(too_large)?;
+    }
+    writer.write_all(&central)?;
+
+    let count = files.len() as u16;
+    let mut end = Vec::with_capacity(22);
+    end.extend_from_slice(&0x06054b50u32.to_le_bytes());
+    end.extend_from_slice(&[0; 4]);
+    end.extend_from_slice(&count.to_le_bytes());
+    end.extend_from_slice(&count.to_le_bytes());
+    end.extend_from_slice(&(central.len() as u32).to_le_bytes());
+    end.extend_from_slice(&offset.to_le_bytes());
+    end.extend_from_slice(&0u16.to_le_bytes());
+    writer.write_all(&end)
+}
+
+// CRC-32 as used by zip, bit by bit since the archives are written rarely
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb88320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+// Appends the state of selected bodies over a whole run to one file, one row per body and frame
+// with `frame`, `time` and `id` before the columns of `write_state`. As .npy the rows form a
+// `(rows, columns)` f64 array, so that frames and ids stay exact, whose shape is filled in by
+// `finish`. Trajectories can't be written as .npz.
+pub struct TrajectoryWriter<W: Write + Seek> {
+    writer: W,
+    format: Format,
+    columns: usize,
+    rows: usize,
+}
+
+impl<W: Write + Seek> TrajectoryWriter<W> {
+    pub fn new(mut writer: W, format: Format, dimensions: usize) -> io::Result<Self> {
+        let mut columns = vec!["frame".to_string(), "time".to_string(), "id".to_string()];
+        columns.extend(self::columns(dimensions));
+
+        match format {
+            Format::Csv => write_csv_row(&mut writer, &columns)?,
+            Format::Npy => {
+                writer.write_all(&npy_header::<f64>(&[0, columns.len()], TRAJECTORY_HEADER))?
+            }
+            Format::Npz => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "trajectories can only be written as .csv or .npy",
+                ))
+            }
+        }
+
+        Ok(Self {
+            writer,
+            format,
+            columns: columns.len(),
+            rows: 0,
+        })
+    }
+
+    // Continues `existing`, a trajectory of an earlier run, after a restart from `frame`. Its rows
+    // up to `frame` are written again and later ones are dropped. A .npy header that was never
+    // filled in, because the run stopped early, is fine since the rows are counted from the data.
+    pub fn resume(
+        writer: W,
+        format: Format,
+        dimensions: usize,
+        existing: &[u8],
+        frame: usize,
+    ) -> io::Result<Self> {
+        let invalid = |reason| io::Error::new(io::ErrorKind::InvalidData, reason);
+        let mut trajectory = Self::new(writer, format, dimensions)?;
+
+        match format {
+            Format::Csv => {
+                let existing = std::str::from_utf8(existing)
+                    .map_err(|_| invalid("existing trajectory is not valid UTF-8"))?;
+                let mut lines = existing.lines();
+                let header = lines.next().unwrap_or_default();
+                if header.split(',').count() != trajectory.columns {
+                    return Err(invalid("existing trajectory has different columns"));
+                }
+
+                for line in lines.filter(|line| !line.is_empty()) {
+                    let row_frame = line.split(',').next().and_then(|f| f.parse::<usize>().ok());
+                    match row_frame {
+                        Some(row_frame) if row_frame > frame => break,
+                        Some(_) => writeln!(trajectory.writer, "{line}")?,
+                        None => return Err(invalid("existing trajectory has a malformed row")),
+                    }
+                    trajectory.rows += 1;
+                }
+            }
+            _ => {
+                let header = npy_header::<f64>(&[0, trajectory.columns], TRAJECTORY_HEADER);
+                let shape = header.iter().position(|&b| b == b'(').unwrap();
+                let matches = existing.len() >= TRAJECTORY_HEADER
+                    && existing[..shape] == header[..shape]
+                    && String::from_utf8_lossy(&existing[shape..TRAJECTORY_HEADER])
+                        .split(')')
+                        .next()
+                        .is_some_and(|dims| dims.ends_with(&format!(", {}", trajectory.columns)));
+                if !matches {
+                    return Err(invalid("existing trajectory has a different header"));
+                }
+
+                let row_len = trajectory.columns * mem::size_of::<f64>();
+                for row in existing[TRAJECTORY_HEADER..].chunks_exact(row_len) {
+                    let row_frame = f64::from_le_bytes(row[..8].try_into().unwrap());
+                    if row_frame > frame as f64 {
+                        break;
+                    }
+                    trajectory.writer.write_all(row)?;
+                    trajectory.rows += 1;
+                }
+            }
+        }
+
+        Ok(trajectory)
+    }
+
+    // `tracked` pairs the id written for each body with its index in `bodies`
+    pub fn write<V: Vector>(
+        &mut self,
+        frame: usize,
+        time: f64,
+        bodies: &[Body<V>],
+        tracked: &[(u64, usize)],
+    ) -> io::Result<()> {
+        let mut state = Vec::with_capacity(self.columns - 3);
+        let mut bytes = Vec::new();
+        for &(id, index) in tracked {
+            state.clear();
+            push_row(&mut state, &bodies[index]);
+
+            match self.format {
+                Format::Csv => {
+                    write!(self.writer, "{frame},{time},{id},")?;
+                    write_csv_row(&mut self.writer, &state)?;
+                }
+                _ => {
+                    bytes.clear();
+                    for value in [frame as f64, time, id as f64] {
+                        value.extend_le(&mut bytes);
+                    }
+                    for &value in &state {
+                        widen(value).extend_le(&mut bytes);
+                    }
+                    self.writer.write_all(&bytes)?;
+                }
+            }
+            self.rows += 1;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.format == Format::Npy {
+            let header = npy_header::<f64>(&[self.rows, self.columns], TRAJECTORY_HEADER);
+            self.writer.seek(SeekFrom::Start(0))?;
+            self.writer.write_all(&header)?;
+            self.writer.seek(SeekFrom::End(0))?;
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::float::{Vec2, Vec3};
+
+    // Header dictionary and data of a .npy file, checking the parts of the format `numpy.load`
+    // relies on
+    fn read_npy(bytes: &[u8]) -> (String, &[u8]) {
+        assert_eq!(&bytes[..8], b"\x93NUMPY\x01\x00");
+        let len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        assert_eq!((10 + len) % 64, 0, "data must be 64-byte aligned");
+
+        let header = std::str::from_utf8(&bytes[10..10 + len]).unwrap();
+        assert!(header.ends_with('\n'));
+        (header.trim_end().to_string(), &bytes[10 + len..])
+    }
+
+    fn floats(data: &[u8]) -> Vec<Float> {
+        data.chunks_exact(mem::size_of::<Float>())
+            .map(|bytes| Float::from_le_bytes(bytes.try_into().unwrap()))
+            .collect()
+    }
+
+    fn doubles(data: &[u8]) -> Vec<f64> {
+        data.chunks_exact(8)
+            .map(|bytes| f64::from_le_bytes(bytes.try_into().unwrap()))
+            .collect()
+    }
+
+    fn descr() -> &'static str {
+        <Float as Element>::DESCR
+    }
+
+    fn bodies() -> Vec<Body> {
+        (0..3)
+            .map(|i| {
+                let i = i as Float;
+                let mut body = Body::new(Vec2::new(i, -i), Vec2::new(10.0 + i, 20.0), 1.0 + i, 0.5);
+                body.acc = Vec2::new(-i, 2.0 * i);
+                body
+            })
+            .collect()
+    }
+
+    fn u16_at(bytes: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap())
+    }
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn npy_has_shape_and_data() {
+        let data = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let mut bytes = Vec::new();
+        write_npy(&mut bytes, &[3, 2], &data).unwrap();
+
+        let (header, body) = read_npy(&bytes);
+        let expected = format!(
+            "{{'descr': '{}', 'fortran_order': False, 'shape': (3, 2), }}",
+            descr()
+        );
+        assert_eq!(header, expected);
+        assert_eq!(floats(body), data);
+
+        let mut bytes = Vec::new();
+        write_npy(&mut bytes, &[6], &data).unwrap();
+        assert!(read_npy(&bytes).0.contains("'shape': (6,)"));
+    }
+
+    #[test]
+    fn npy_state_has_a_row_per_body() {
+        let bodies = bodies();
+        let mut bytes = Vec::new();
+        write_state(&bodies, Format::Npy, &mut bytes).unwrap();
+
+        let (header, data) = read_npy(&bytes);
+        assert!(header.contains("'shape': (3, 8)"));
+        let data = floats(data);
+        assert_eq!(data[8..16], [1.0, -1.0, 11.0, 20.0, -1.0, 2.0, 2.0, 0.5]);
+    }
+
+    #[test]
+    fn crc32_matches_the_standard_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn npz_is_a_stored_zip_of_arrays() {
+        let bodies = (0..4)
+            .map(|i| {
+                let i = i as Float;
+                Body::new(Vec3::new(i, 0.0, -i), Vec3::zero(), 1.0, i)
+            })
+            .collect::<Vec<_>>();
+        let mut bytes = Vec::new();
+        write_state(&bodies, Format::Npz, &mut bytes).unwrap();
+
+        // End of central directory record
+        let end = bytes.len() - 22;
+        assert_eq!(u32_at(&bytes, end), 0x06054b50);
+        let count = u16_at(&bytes, end + 10) as usize;
+        let central_size = u32_at(&bytes, end + 12) as usize;
+        let central_offset = u32_at(&bytes, end + 16) as usize;
+        assert_eq!(count, 5);
+        assert_eq!(central_offset + central_size, end);
+
+        let mut entry = central_offset;
+        let mut names = Vec::new();
+        for _ in 0..count {
+            assert_eq!(u32_at(&bytes, entry), 0x02014b50);
+            let name_len = u16_at(&bytes, entry + 28) as usize;
+            let local = u32_at(&bytes, entry + 42) as usize;
+            let name = std::str::from_utf8(&bytes[entry + 46..entry + 46 + name_len]).unwrap();
+
+            // The local header repeats the central one, followed by the uncompressed file
+            assert_eq!(u32_at(&bytes, local), 0x04034b50);
+            assert_eq!(bytes[local + 4..local + 30], bytes[entry + 6..entry + 32]);
+            assert_eq!(u16_at(&bytes, local + 8), 0, "files must be stored");
+            let crc = u32_at(&bytes, local + 14);
+            let size = u32_at(&bytes, local + 18) as usize;
+            assert_eq!(u32_at(&bytes, local + 22) as usize, size);
+
+            let start = local + 30 + name_len;
+            let file = &bytes[start..start + size];
+            assert_eq!(crc32(file), crc);
+
+            let (header, data) = read_npy(file);
+            let data = floats(data);
+            match name {
+                "pos.npy" => {
+                    assert!(header.contains("'shape': (4, 3)"));
+                    assert_eq!(data[3..6], [1.0, 0.0, -1.0]);
+                }
+                "vel.npy" | "acc.npy" => assert!(header.contains("'shape': (4, 3)")),
+                "mass.npy" => assert_eq!(data, [1.0; 4]),
+                "radius.npy" => {
+                    assert!(header.contains("'shape': (4,)"));
+                    assert_eq!(data, [0.0, 1.0, 2.0, 3.0]);
+                }
+                _ => panic!("unexpected file {name}"),
+            }
+
+            names.push(name.to_string());
+            entry += 46 + name_len;
+        }
+        assert_eq!(
+            names,
+            ["pos.npy", "vel.npy", "acc.npy", "mass.npy", "radius.npy"]
+        );
+    }
+
+    #[test]
+    fn finish_fills_in_the_trajectory_shape() {
+        let bodies = bodies();
+        let tracked = [(7, 1), (9, 2)];
+        let mut trajectory =
+            TrajectoryWriter::new(Cursor::new(Vec::new()), Format::Npy, 2).unwrap();
+        for frame in 0..3 {
+            trajectory
+                .write(frame, frame as f64 * 0.5, &bodies, &tracked)
+                .unwrap();
+        }
+        let bytes = trajectory.finish().unwrap().into_inner();
+
+        let (header, data) = read_npy(&bytes);
+        assert_eq!(bytes.len() - data.len(), TRAJECTORY_HEADER);
+        assert!(header.contains("'descr': '<f8'"));
+        assert!(header.contains("'shape': (6, 11)"));
+
+        let data = doubles(data);
+        assert_eq!(data.len(), 6 * 11);
+        assert_eq!(data[11 * 5..11 * 5 + 5], [2.0, 1.0, 9.0, 2.0, -2.0]);
+    }
+
+    #[test]
+    fn csv_trajectory_has_a_row_per_body_and_frame() {
+        let bodies = bodies();
+        let mut trajectory =
+            TrajectoryWriter::new(Cursor::new(Vec::new()), Format::Csv, 2).unwrap();
+        trajectory.write(4, 2.0, &bodies, &[(3, 1)]).unwrap();
+        let bytes = trajectory.finish().unwrap().into_inner();
+
+        let text = String::from_utf8(bytes).unwrap();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "frame,time,id,x,y,vx,vy,ax,ay,mass,radius");
+        assert_eq!(lines[1], "4,2,3,1,-1,11,20,-1,2,2,0.5");
+        assert_eq!(lines.len(), 2);
+    }
+
+    #[test]
+    fn resume_drops_rows_after_the_restart() {
+        let bodies = bodies();
+        for format in [Format::Csv, Format::Npy] {
+            let mut trajectory = TrajectoryWriter::new(Cursor::new(Vec::new()), format, 2).unwrap();
+            for frame in 0..5 {
+                trajectory.write(frame, 0.0, &bodies, &[(0, 0)]).unwrap();
+            }
+            // Left unfinished, as by a run that was killed
+            let existing = trajectory.writer.into_inner();
+
+            let resumed =
+                TrajectoryWriter::resume(Cursor::new(Vec::new()), format, 2, &existing, 2).unwrap();
+            assert_eq!(resumed.rows, 3);
+            let bytes = resumed.finish().unwrap().into_inner();
+
+            let mut expected = TrajectoryWriter::new(Cursor::new(Vec::new()), format, 2).unwrap();
+            for frame in 0..3 {
+                expected.write(frame, 0.0, &bodies, &[(0, 0)]).unwrap();
+            }
+            assert_eq!(bytes, expected.finish().unwrap().into_inner());
+
+            let wrong = TrajectoryWriter::resume(Cursor::new(Vec::new()), format, 3, &existing, 2);
+            assert!(wrong.is_err());
+        }
+    }
+}
@@ -0,0 +1,62 @@
+// Scalar and vector types of the simulation state. These are `f32` by default, which is enough
+// for interactive viewing, and `f64` with the `f64` feature for long or far-from-origin runs.
+
+#[cfg(not(feature = "f64"))]
+mod types {
+    pub type Float = f32;
+    pub use std::f32::consts;
+    pub use ultraviolet::{Mat3, Vec2, Vec3};
+
+    // Uniform in [0, 1)
+    pub fn random() -> Float {
+        fastrand::f32()
+    }
+
+    pub fn widen(x: Float) -> f64 {
+        x as f64
+    }
+
+    // Conversions for drawing, which is always done in f32
+    pub fn narrow(x: Float) -> f32 {
+        x
+    }
+
+    pub fn to_f32(v: Vec2) -> ultraviolet::Vec2 {
+        v
+    }
+
+    pub fn from_f32(v: ultraviolet::Vec2) -> Vec2 {
+        v
+    }
+}
+
+#[cfg(feature = "f64")]
+mod types {
+    pub type Float = f64;
+    pub use std::f64::consts;
+    pub use ultraviolet::{DMat3 as Mat3, DVec2 as Vec2, DVec3 as Vec3};
+
+    // Uniform in [0, 1)
+    pub fn random() -> Float {
+        fastrand::f64()
+    }
+
+    pub fn widen(x: Float) -> f64 {
+        x
+    }
+
+    // Conversions for drawing, which is always done in f32
+    pub fn narrow(x: Float) -> f32 {
+        x as f32
+    }
+
+    pub fn to_f32(v: Vec2) -> ultraviolet::Vec2 {
+        ultraviolet::Vec2::new(v.x as f32, v.y as f32)
+    }
+
+    pub fn from_f32(v: ultraviolet::Vec2) -> Vec2 {
+        Vec2::new(v.x as f64, v.y as f64)
+    }
+}
+
+pub use types::*;
@@ -0,0 +1,598 @@
+use std::mem;
+
+use serde::{Deserialize, Serialize};
+use ultraviolet::DVec2;
+
+use crate::{
+    body::Body,
+    float::{widen, Float, Vec2},
+    parallel,
+    quadtree::{Node, Quadtree},
+    solver::GravitySolver,
+};
+
+// Highest `FmmConfig::order`, which sizes the buffers the expansions are computed in
+pub const MAX_ORDER: usize = 16;
+const MAX_TERMS: usize = terms(MAX_ORDER);
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FmmConfig {
+    // Highest order of the multipole and local expansions, at most `MAX_ORDER`
+    pub order: usize,
+    // Cells interact through their expansions when `(r_a + r_b) < theta * distance`
+    pub theta: Float,
+    pub leaf_capacity: usize,
+}
+
+impl Default for FmmConfig {
+    fn default() -> Self {
+        Self {
+            order: 4,
+            theta: 0.5,
+            leaf_capacity: 16,
+        }
+    }
+}
+
+// Dual-tree Fast Multipole Method on top of the quadtree layout.
+//
+// Expansions are Cartesian Taylor series of the 1/r potential in the plane, stored per node as
+// coefficients `c[i, j]` of `x^i y^j` for `i + j <= order`. Far-field interactions use the
+// unsoftened potential, near-field ones use `quadtree.softening` like every other solver.
+pub struct Fmm {
+    pub quadtree: Quadtree,
+    pub order: usize,
+    pub theta: Float,
+    multipoles: Vec<f64>,
+    locals: Vec<f64>,
+    radii: Vec<Float>,
+    // Per point in `quadtree.points`
+    accs: Vec<Vec2>,
+    potentials: Vec<Float>,
+    // Per body, its index in `quadtree.points`
+    slots: Vec<usize>,
+    binomials: Vec<Vec<f64>>,
+}
+
+impl Fmm {
+    pub fn new(epsilon: Float, config: FmmConfig) -> Self {
+        let mut quadtree = Quadtree::new(0.0, epsilon);
+        quadtree.leaf_capacity = config.leaf_capacity.max(1);
+
+        let order = config.order;
+        assert!(order <= MAX_ORDER, "'order' can be at most {MAX_ORDER}");
+        let binomials = (0..=order)
+            .map(|n| {
+                let mut row = vec![1.0; n + 1];
+                for k in 1..n {
+                    row[k] = row[k - 1] * (n + 1 - k) as f64 / k as f64;
+                }
+                row
+            })
+            .collect();
+
+        Self {
+            quadtree,
+            order,
+            theta: config.theta,
+            multipoles: Vec::new(),
+            locals: Vec::new(),
+            radii: Vec::new(),
+            accs: Vec::new(),
+            potentials: Vec::new(),
+            slots: Vec::new(),
+            binomials,
+        }
+    }
+
+    fn terms(&self) -> usize {
+        terms(self.order)
+    }
+
+    // Index of the coefficient of `x^i y^j`, grouped by total order
+    fn index(i: usize, j: usize) -> usize {
+        let n = i + j;
+        n * (n + 1) / 2 + j
+    }
+
+    fn binomial(&self, n: usize, k: usize) -> f64 {
+        self.binomials[n][k]
+    }
+
+    // Taylor coefficients `∂^(i, j) (1/r) / (i! j!)` at `r`, from the recurrence
+    // n r² a(k) + (2n - 1) Σ r_d a(k - e_d) + (n - 1) Σ a(k - 2 e_d) = 0
+    fn derivatives(&self, r: DVec2) -> [f64; MAX_TERMS] {
+        let mut a = [0.0; MAX_TERMS];
+        let r_sq = r.mag_sq();
+        a[0] = 1.0 / r_sq.sqrt();
+
+        for n in 1..=self.order {
+            for j in 0..=n {
+                let i = n - j;
+                let mut sum = 0.0;
+                if i >= 1 {
+                    sum += (2 * n - 1) as f64 * r.x * a[Self::index(i - 1, j)];
+                }
+                if j >= 1 {
+                    sum += (2 * n - 1) as f64 * r.y * a[Self::index(i, j - 1)];
+                }
+                if i >= 2 {
+                    sum += (n - 1) as f64 * a[Self::index(i - 2, j)];
+                }
+                if j >= 2 {
+                    sum += (n - 1) as f64 * a[Self::index(i, j - 2)];
+                }
+                a[Self::index(i, j)] = -sum / (n as f64 * r_sq);
+            }
+        }
+
+        a
+    }
+
+    fn center(node: &Node) -> DVec2 {
+        DVec2::new(widen(node.pos.x), widen(node.pos.y))
+    }
+
+    fn upward(&mut self, threads: usize) {
+        let terms = self.terms();
+
+        // P2M, leaves spread across threads
+        let mut multipoles = mem::take(&mut self.multipoles);
+        let fmm = &*self;
+        parallel::for_each_chunk_indexed(&mut multipoles, threads, 64 * terms, |start, chunk| {
+            for (k, multipole) in chunk.chunks_mut(terms).enumerate() {
+                fmm.p2m(start / terms + k, multipole);
+            }
+        });
+        self.multipoles = multipoles;
+
+        // M2M, children before parents and one level at a time
+        for level in self.quadtree.levels().into_iter().rev() {
+            let mut sums = vec![0.0; level.len() * terms];
+            let fmm = &*self;
+            parallel::for_each_chunk_indexed(&mut sums, threads, 64 * terms, |start, chunk| {
+                for (k, sum) in chunk.chunks_mut(terms).enumerate() {
+                    fmm.m2m(level[start / terms + k], sum);
+                }
+            });
+
+            for (&node, sum) in level.iter().zip(sums.chunks(terms)) {
+                self.multipoles[node * terms..(node + 1) * terms].copy_from_slice(sum);
+            }
+        }
+    }
+
+    // P2M: adds the bodies of leaf `node` to `multipole`
+    fn p2m(&self, node: usize, multipole: &mut [f64]) {
+        let n = &self.quadtree.nodes[node];
+        if n.is_branch() || n.is_empty() {
+            return;
+        }
+
+        let center = Self::center(n);
+        for p in &self.quadtree.points[n.bodies.clone()] {
+            let (x, y) = powers(
+                self.order,
+                DVec2::new(widen(p.pos.x), widen(p.pos.y)) - center,
+            );
+            for n in 0..=self.order {
+                for j in 0..=n {
+                    multipole[Self::index(n - j, j)] += widen(p.mass) * x[n - j] * y[j];
+                }
+            }
+        }
+    }
+
+    // M2M: adds the multipoles of the children of branch `node` to `multipole`
+    fn m2m(&self, node: usize, multipole: &mut [f64]) {
+        let terms = self.terms();
+        let nodes = &self.quadtree.nodes;
+        let center = Self::center(&nodes[node]);
+        let children = nodes[node].children;
+
+        for (child, c) in nodes.iter().enumerate().skip(children).take(4) {
+            if c.is_empty() {
+                continue;
+            }
+
+            let (x, y) = powers(self.order, Self::center(c) - center);
+            for n in 0..=self.order {
+                for j in 0..=n {
+                    let i = n - j;
+                    let mut sum = 0.0;
+                    for gi in 0..=i {
+                        for gj in 0..=j {
+                            sum += self.binomial(i, gi)
+                                * self.binomial(j, gj)
+                                * self.multipoles[child * terms + Self::index(gi, gj)]
+                                * x[i - gi]
+                                * y[j - gj];
+                        }
+                    }
+                    multipole[Self::index(i, j)] += sum;
+                }
+            }
+        }
+    }
+
+    // M2L: adds the field of the multipole of `source` to `local`, the expansion of `target`
+    fn m2l(&self, target: usize, source: usize, local: &mut [f64]) {
+        let terms = self.terms();
+        let nodes = &self.quadtree.nodes;
+        let r = Self::center(&nodes[target]) - Self::center(&nodes[source]);
+        let a = self.derivatives(r);
+
+        for bn in 0..=self.order {
+            for bj in 0..=bn {
+                let bi = bn - bj;
+                let mut sum = 0.0;
+                for an in 0..=self.order - bn {
+                    let sign = if an % 2 == 0 { 1.0 } else { -1.0 };
+                    for aj in 0..=an {
+                        let ai = an - aj;
+                        sum += sign
+                            * self.multipoles[source * terms + Self::index(ai, aj)]
+                            * self.binomial(ai + bi, ai)
+                            * self.binomial(aj + bj, aj)
+                            * a[Self::index(ai + bi, aj + bj)];
+                    }
+                }
+                local[Self::index(bi, bj)] -= sum;
+            }
+        }
+    }
+
+    // P2P: adds the forces of the bodies of leaf `source` on those of leaf `target`, whose
+    // accelerations and potentials are `accs` and `potentials`
+    fn p2p(&self, target: usize, source: usize, accs: &mut [Vec2], potentials: &mut [Float]) {
+        let softening = self.quadtree.softening;
+        let targets = &self.quadtree.points[self.quadtree.nodes[target].bodies.clone()];
+        let sources = &self.quadtree.points[self.quadtree.nodes[source].bodies.clone()];
+
+        for (i, t) in targets.iter().enumerate() {
+            let epsilon = self.quadtree.epsilon.max(t.softening);
+            let mut acc = Vec2::zero();
+            let mut potential = 0.0;
+            for p in sources {
+                let d = p.pos - t.pos;
+                let d_sq = d.mag_sq();
+                let e = epsilon.max(p.softening);
+                acc += softening.acc(d, d_sq, p.mass, e);
+                potential += softening.potential(d_sq, p.mass, e);
+            }
+            accs[i] += acc;
+            potentials[i] += potential;
+        }
+    }
+
+    // Walks both trees at once to find the pairs of cells that interact through their expansions
+    // and the pairs of leaves that interact directly
+    fn interact(&self) -> (Interactions, Interactions) {
+        let mut far = Vec::new();
+        let mut near = Vec::new();
+
+        let mut stack = vec![(Quadtree::ROOT, Quadtree::ROOT)];
+        while let Some((target, source)) = stack.pop() {
+            let t = &self.quadtree.nodes[target];
+            let s = &self.quadtree.nodes[source];
+            if t.is_empty() || s.is_empty() {
+                continue;
+            }
+
+            if target == source {
+                if t.is_leaf() {
+                    near.push((target, target));
+                } else {
+                    for i in 0..4 {
+                        for j in 0..4 {
+                            stack.push((t.children + i, t.children + j));
+                        }
+                    }
+                }
+                continue;
+            }
+
+            let (r_t, r_s) = (self.radii[target], self.radii[source]);
+            let distance = (t.pos - s.pos).mag();
+
+            // Expansions are unsoftened, so cells whose bodies could be within a few softening
+            // lengths of each other are always opened
+            let e = self.quadtree.epsilon.max(t.softening).max(s.softening);
+            let softened = distance - r_t - r_s < 3.0 * e;
+
+            if r_t + r_s < self.theta * distance && !softened {
+                far.push((target, source));
+            } else if t.is_leaf() && s.is_leaf() {
+                near.push((target, source));
+            } else if t.is_leaf() || (s.is_branch() && r_s >= r_t) {
+                for i in 0..4 {
+                    stack.push((target, s.children + i));
+                }
+            } else {
+                for i in 0..4 {
+                    stack.push((t.children + i, source));
+                }
+            }
+        }
+
+        let nodes = self.quadtree.nodes.len();
+        (
+            Interactions::new(&far, nodes),
+            Interactions::new(&near, nodes),
+        )
+    }
+
+    // M2L for every cell, each summing its sources in the order the walk found them
+    fn translate(&mut self, far: &Interactions, threads: usize) {
+        let terms = self.terms();
+        let mut locals = mem::take(&mut self.locals);
+        let fmm = &*self;
+        parallel::for_each_chunk_indexed(&mut locals, threads, 64 * terms, |start, chunk| {
+            for (k, local) in chunk.chunks_mut(terms).enumerate() {
+                let target = start / terms + k;
+                for &source in far.of(target) {
+                    fmm.m2l(target, source, local);
+                }
+            }
+        });
+        self.locals = locals;
+    }
+
+    fn downward(&mut self, threads: usize) {
+        let terms = self.terms();
+
+        // L2L, parents before children and one level at a time
+        for level in self.quadtree.levels() {
+            let mut sums = vec![0.0; level.len() * 4 * terms];
+            let fmm = &*self;
+            parallel::for_each_chunk_indexed(&mut sums, threads, 64 * 4 * terms, |start, chunk| {
+                for (k, sums) in chunk.chunks_mut(4 * terms).enumerate() {
+                    fmm.l2l(level[start / (4 * terms) + k], sums);
+                }
+            });
+
+            for (&node, sums) in level.iter().zip(sums.chunks(4 * terms)) {
+                let children = self.quadtree.nodes[node].children;
+                for (child, sum) in (children..children + 4).zip(sums.chunks(terms)) {
+                    if self.quadtree.nodes[child].is_empty() {
+                        continue;
+                    }
+                    let local = &mut self.locals[child * terms..(child + 1) * terms];
+                    for (c, sum) in local.iter_mut().zip(sum) {
+                        *c += sum;
+                    }
+                }
+            }
+        }
+    }
+
+    // L2L: the local expansion of branch `node` about the centers of its children, one block of
+    // `sums` per child
+    fn l2l(&self, node: usize, sums: &mut [f64]) {
+        let terms = self.terms();
+        let nodes = &self.quadtree.nodes;
+        let center = Self::center(&nodes[node]);
+        let children = nodes[node].children;
+        let local = &self.locals[node * terms..(node + 1) * terms];
+
+        for (c, sum) in nodes[children..children + 4]
+            .iter()
+            .zip(sums.chunks_mut(terms))
+        {
+            if c.is_empty() {
+                continue;
+            }
+
+            let (x, y) = powers(self.order, Self::center(c) - center);
+            for gn in 0..=self.order {
+                for gj in 0..=gn {
+                    let gi = gn - gj;
+                    let mut s = 0.0;
+                    for bi in gi..=self.order {
+                        for bj in gj..=self.order - bi {
+                            s += local[Self::index(bi, bj)]
+                                * self.binomial(bi, gi)
+                                * self.binomial(bj, gj)
+                                * x[bi - gi]
+                                * y[bj - gj];
+                        }
+                    }
+                    sum[Self::index(gi, gj)] = s;
+                }
+            }
+        }
+    }
+
+    // P2P then L2P for every leaf, with the leaves spread across threads
+    fn evaluate(&mut self, near: &Interactions, threads: usize) {
+        let nodes = &self.quadtree.nodes;
+        let mut leaves: Vec<usize> = (0..nodes.len())
+            .filter(|&node| nodes[node].is_leaf() && !nodes[node].is_empty())
+            .collect();
+        leaves.sort_unstable_by_key(|&leaf| nodes[leaf].bodies.start);
+
+        // Leaves own disjoint ranges of points, so each gets its own slices of the results
+        let mut accs = mem::take(&mut self.accs);
+        let mut potentials = mem::take(&mut self.potentials);
+        let mut work = Vec::with_capacity(leaves.len());
+        let (mut acc_rest, mut potential_rest, mut offset) =
+            (&mut accs[..], &mut potentials[..], 0);
+        for leaf in leaves {
+            let range = nodes[leaf].bodies.clone();
+            let (_, tail) = acc_rest.split_at_mut(range.start - offset);
+            let (leaf_accs, tail) = tail.split_at_mut(range.len());
+            acc_rest = tail;
+            let (_, tail) = potential_rest.split_at_mut(range.start - offset);
+            let (leaf_potentials, tail) = tail.split_at_mut(range.len());
+            potential_rest = tail;
+            offset = range.end;
+            work.push((leaf, leaf_accs, leaf_potentials));
+        }
+
+        let fmm = &*self;
+        parallel::for_each_chunk(&mut work, threads, 16, |work| {
+            for (leaf, accs, potentials) in work {
+                for &source in near.of(*leaf) {
+                    fmm.p2p(*leaf, source, accs, potentials);
+                }
+                fmm.l2p(*leaf, accs, potentials);
+            }
+        });
+
+        self.accs = accs;
+        self.potentials = potentials;
+    }
+
+    // L2P: evaluates the local expansion of leaf `node` at its bodies
+    fn l2p(&self, node: usize, accs: &mut [Vec2], potentials: &mut [Float]) {
+        let terms = self.terms();
+        let n = &self.quadtree.nodes[node];
+        let center = Self::center(n);
+        let local = &self.locals[node * terms..(node + 1) * terms];
+
+        for (i, point) in self.quadtree.points[n.bodies.clone()].iter().enumerate() {
+            let p = point.pos;
+            let (x, y) = powers(self.order, DVec2::new(widen(p.x), widen(p.y)) - center);
+
+            let mut potential = 0.0;
+            let mut grad = DVec2::zero();
+            for bn in 0..=self.order {
+                for bj in 0..=bn {
+                    let bi = bn - bj;
+                    let c = local[Self::index(bi, bj)];
+                    potential += c * x[bi] * y[bj];
+                    if bi >= 1 {
+                        grad.x += c * bi as f64 * x[bi - 1] * y[bj];
+                    }
+                    if bj >= 1 {
+                        grad.y += c * bj as f64 * x[bi] * y[bj - 1];
+                    }
+                }
+            }
+
+            accs[i] -= Vec2::new(grad.x as Float, grad.y as Float);
+            potentials[i] += potential as Float;
+        }
+    }
+}
+
+// Pairs of nodes found by `Fmm::interact`, grouped by target in the order they were found so that
+// every target sums its sources in the same order whatever the thread count
+struct Interactions {
+    // `sources[starts[t]..starts[t + 1]]` are the sources of target `t`
+    starts: Vec<usize>,
+    sources: Vec<usize>,
+}
+
+impl Interactions {
+    fn new(pairs: &[(usize, usize)], nodes: usize) -> Self {
+        let mut starts = vec![0; nodes + 1];
+        for &(target, _) in pairs {
+            starts[target + 1] += 1;
+        }
+        for i in 0..nodes {
+            starts[i + 1] += starts[i];
+        }
+
+        let mut next = starts.clone();
+        let mut sources = vec![0; pairs.len()];
+        for &(target, source) in pairs {
+            sources[next[target]] = source;
+            next[target] += 1;
+        }
+
+        Self { starts, sources }
+    }
+
+    fn of(&self, target: usize) -> &[usize] {
+        &self.sources[self.starts[target]..self.starts[target + 1]]
+    }
+}
+
+impl GravitySolver for Fmm {
+    fn build(&mut self, bodies: &mut [Body], threads: usize) {
+        self.quadtree.build_morton_parallel(bodies, threads);
+
+        let nodes = self.quadtree.nodes.len();
+        let terms = self.terms();
+        self.multipoles.clear();
+        self.multipoles.resize(nodes * terms, 0.0);
+        self.locals.clear();
+        self.locals.resize(nodes * terms, 0.0);
+        self.radii.clear();
+        self.radii
+            .extend(self.quadtree.nodes.iter().map(Node::bmax));
+
+        let points = self.quadtree.points.len();
+        self.accs.clear();
+        self.accs.resize(points, Vec2::zero());
+        self.potentials.clear();
+        self.potentials.resize(points, 0.0);
+
+        self.slots.clear();
+        self.slots.resize(points, 0);
+        for (k, p) in self.quadtree.points.iter().enumerate() {
+            self.slots[p.index] = k;
+        }
+
+        self.upward(threads);
+        let (far, near) = self.interact();
+        self.translate(&far, threads);
+        self.downward(threads);
+        self.evaluate(&near, threads);
+    }
+
+    fn attract(
+        &self,
+        bodies: &mut [Body],
+        active: &(dyn Fn(&Body) -> bool + Sync),
+        threads: usize,
+    ) {
+        if self.quadtree.nodes.is_empty() {
+            return;
+        }
+
+        parallel::for_each_chunk_indexed(bodies, threads, 4096, |start, bodies| {
+            for (i, body) in bodies.iter_mut().enumerate() {
+                if active(body) {
+                    body.acc = self.accs[self.slots[start + i]];
+                }
+            }
+        });
+    }
+
+    fn potentials(&self, bodies: &[Body], threads: usize) -> Option<Vec<Float>> {
+        if self.quadtree.nodes.is_empty() {
+            return None;
+        }
+
+        let mut potentials = vec![0.0; bodies.len()];
+        parallel::for_each_chunk_indexed(&mut potentials, threads, 4096, |start, potentials| {
+            for (i, potential) in potentials.iter_mut().enumerate() {
+                *potential = self.potentials[self.slots[start + i]];
+            }
+        });
+        Some(potentials)
+    }
+
+    fn nodes(&self) -> &[Node] {
+        &self.quadtree.nodes
+    }
+}
+
+// Number of coefficients of an expansion of order `order`
+const fn terms(order: usize) -> usize {
+    (order + 1) * (order + 2) / 2
+}
+
+// `v.x^k` and `v.y^k` for `k <= order`
+fn powers(order: usize, v: DVec2) -> ([f64; MAX_ORDER + 1], [f64; MAX_ORDER + 1]) {
+    let mut x = [1.0; MAX_ORDER + 1];
+    let mut y = [1.0; MAX_ORDER + 1];
+    for n in 1..=order {
+        x[n] = x[n - 1] * v.x;
+        y[n] = y[n - 1] * v.y;
+    }
+    (x, y)
+}
@@ -0,0 +1,358 @@
+use std::{
+    collections::HashSet,
+    fmt, fs,
+    io::{self, BufRead, BufReader, Read},
+    path::Path,
+};
+
+use serde::Deserialize;
+
+use crate::{
+    body::Body,
+    float::{Float, Vec2},
+};
+
+const REQUIRED: [&str; 6] = ["x", "y", "vx", "vy", "mass", "radius"];
+
+// Bodies read from a particle catalog, with the optional per-body columns kept alongside them in
+// the same order
+pub struct Catalog {
+    pub bodies: Vec<Body>,
+    // From the `id` column, or the 0-based row index if there is none
+    pub ids: Vec<u64>,
+    // From the `type` column
+    pub types: Vec<Option<String>>,
+}
+
+impl Catalog {
+    // Comma-separated values with a header row naming the columns, in any order. Fields can be
+    // wrapped in double quotes to hold commas, with `""` for a quote inside them. Blank lines and
+    // lines starting with `#` are skipped, and columns other than `x`, `y`, `vx`, `vy`, `mass`,
+    // `radius`, `id` and `type` are ignored.
+    pub fn read_csv(reader: impl Read) -> Result<Self, ImportError> {
+        let mut lines = BufReader::new(reader)
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line))
+            .filter(|(_, line)| {
+                line.as_ref().map_or(true, |line| {
+                    let line = line.trim_start();
+                    !line.is_empty() && !line.starts_with('#')
+                })
+            });
+
+        let (header_line, header) = match lines.next() {
+            Some((line, header)) => (line, header?),
+            None => return Err(ImportError::Empty),
+        };
+        let columns = split_fields(&header).ok_or(ImportError::Unquoted { line: header_line })?;
+        let find = |name: &str| columns.iter().position(|column| column == name);
+
+        let mut required = [0; REQUIRED.len()];
+        for (index, name) in required.iter_mut().zip(REQUIRED) {
+            *index = find(name).ok_or(ImportError::MissingColumn {
+                line: header_line,
+                column: name,
+            })?;
+        }
+        let (id, kind) = (find("id"), find("type"));
+
+        let mut catalog = Self::new();
+        let mut seen = HashSet::new();
+        for (line, row) in lines {
+            let row = row?;
+            let fields = split_fields(&row).ok_or(ImportError::Unquoted { line })?;
+            if fields.len() != columns.len() {
+                return Err(ImportError::FieldCount {
+                    line,
+                    expected: columns.len(),
+                    found: fields.len(),
+                });
+            }
+
+            let mut values = [0.0; REQUIRED.len()];
+            for ((value, &index), name) in values.iter_mut().zip(&required).zip(REQUIRED) {
+                *value = fields[index].parse().map_err(|_| ImportError::Invalid {
+                    line,
+                    field: name,
+                    value: fields[index].to_string(),
+                    reason: "not a number",
+                })?;
+            }
+            let [x, y, vx, vy, mass, radius] = values;
+
+            let id = match id.map(|index| fields[index].as_str()) {
+                Some(id) if !id.is_empty() => {
+                    Some(id.parse().map_err(|_| ImportError::Invalid {
+                        line,
+                        field: "id",
+                        value: id.to_string(),
+                        reason: "not a non-negative integer",
+                    })?)
+                }
+                _ => None,
+            };
+            let kind = kind
+                .map(|index| fields[index].clone())
+                .filter(|kind| !kind.is_empty());
+
+            let record = Record {
+                x,
+                y,
+                vx,
+                vy,
+                mass,
+                radius,
+                id,
+                kind,
+            };
+            catalog.push(record, line, &mut seen)?;
+        }
+
+        Ok(catalog)
+    }
+
+    // An array of objects with the same fields as the CSV columns, where `id` and `type` may be
+    // left out or null like empty CSV fields. Errors are reported at the line where the offending
+    // object starts.
+    pub fn read_json(mut reader: impl Read) -> Result<Self, ImportError> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+
+        let records: Vec<Record> = serde_json::from_str(&text).map_err(ImportError::Json)?;
+        let lines = object_lines(&text);
+
+        let mut catalog = Self::new();
+        let mut seen = HashSet::new();
+        for (record, line) in records.into_iter().zip(lines) {
+            catalog.push(record, line, &mut seen)?;
+        }
+        Ok(catalog)
+    }
+
+    /// Loads a catalog, picking the format from the `.csv` or `.json` extension.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ImportError> {
+        let path = path.as_ref();
+        let read = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => |file| Self::read_csv(file),
+            Some("json") => |file| Self::read_json(file),
+            _ => return Err(ImportError::UnknownFormat(path.display().to_string())),
+        };
+        read(fs::File::open(path)?)
+    }
+
+    fn new() -> Self {
+        Self {
+            bodies: Vec::new(),
+            ids: Vec::new(),
+            types: Vec::new(),
+        }
+    }
+
+    // Validates a record read from `line`, with `seen` holding the ids of the records before it
+    fn push(
+        &mut self,
+        record: Record,
+        line: usize,
+        seen: &mut HashSet<u64>,
+    ) -> Result<(), ImportError> {
+        let invalid = |field, value: Float, reason| ImportError::Invalid {
+            line,
+            field,
+            value: value.to_string(),
+            reason,
+        };
+
+        let fields = [
+            ("x", record.x),
+            ("y", record.y),
+            ("vx", record.vx),
+            ("vy", record.vy),
+            ("mass", record.mass),
+            ("radius", record.radius),
+        ];
+        for (field, value) in fields {
+            if !value.is_finite() {
+                return Err(invalid(field, value, "must be finite"));
+            }
+        }
+        if record.mass <= 0.0 {
+            return Err(invalid("mass", record.mass, "must be positive"));
+        }
+        if record.radius < 0.0 {
+            return Err(invalid("radius", record.radius, "must not be negative"));
+        }
+
+        let id = record.id.unwrap_or(self.bodies.len() as u64);
+        if !seen.insert(id) {
+            return Err(ImportError::DuplicateId { line, id });
+        }
+
+        let pos = Vec2::new(record.x, record.y);
+        let vel = Vec2::new(record.vx, record.vy);
+        self.bodies
+            .push(Body::new(pos, vel, record.mass, record.radius));
+        self.ids.push(id);
+        self.types.push(record.kind);
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct Record {
+    x: Float,
+    y: Float,
+    vx: Float,
+    vy: Float,
+    mass: Float,
+    radius: Float,
+    #[serde(default)]
+    id: Option<u64>,
+    #[serde(default, rename = "type")]
+    kind: Option<String>,
+}
+
+// Fields of a CSV row, trimmed unless they are quoted, or `None` if a quote is never closed
+fn split_fields(row: &str) -> Option<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut chars = row.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+        let mut field = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next()? {
+                    '"' if chars.next_if_eq(&'"').is_some() => field.push('"'),
+                    '"' => break,
+                    c => field.push(c),
+                }
+            }
+            // Anything between the closing quote and the next comma is kept as well
+            let mut rest = String::new();
+            while let Some(c) = chars.next_if(|&c| c != ',') {
+                rest.push(c);
+            }
+            field.push_str(rest.trim_end());
+        } else {
+            while let Some(c) = chars.next_if(|&c| c != ',') {
+                field.push(c);
+            }
+            field.truncate(field.trim_end().len());
+        }
+        fields.push(field);
+
+        if chars.next().is_none() {
+            return Some(fields);
+        }
+    }
+}
+
+// Line on which each object of the top-level JSON array starts
+fn object_lines(text: &str) -> Vec<usize> {
+    let mut lines = Vec::new();
+    let (mut line, mut depth) = (1, 0);
+    let (mut in_string, mut escaped) = (false, false);
+
+    for c in text.chars() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else {
+            match c {
+                '"' => in_string = true,
+                '{' | '[' => {
+                    if c == '{' && depth == 1 {
+                        lines.push(line);
+                    }
+                    depth += 1;
+                }
+                '}' | ']' => depth -= 1,
+                _ => {}
+            }
+        }
+
+        if c == '\n' {
+            line += 1;
+        }
+    }
+
+    lines
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    Io(io::Error),
+    // Malformed JSON or a field of the wrong type, which `serde_json` reports with its position
+    Json(serde_json::Error),
+    Empty,
+    MissingColumn {
+        line: usize,
+        column: &'static str,
+    },
+    FieldCount {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    // A quoted CSV field without its closing quote
+    Unquoted {
+        line: usize,
+    },
+    Invalid {
+        line: usize,
+        field: &'static str,
+        value: String,
+        reason: &'static str,
+    },
+    DuplicateId {
+        line: usize,
+        id: u64,
+    },
+    UnknownFormat(String),
+}
+
+impl From<io::Error> for ImportError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "failed to read catalog: {e}"),
+            Self::Json(e) => write!(f, "invalid JSON catalog: {e}"),
+            Self::Empty => write!(f, "catalog is empty, expected a header row"),
+            Self::MissingColumn { line, column } => {
+                write!(f, "line {line}: missing column '{column}' in header")
+            }
+            Self::FieldCount {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {line}: expected {expected} fields like the header, found {found}"
+            ),
+            Self::Unquoted { line } => write!(f, "line {line}: quoted field is never closed"),
+            Self::Invalid {
+                line,
+                field,
+                value,
+                reason,
+            } => write!(f, "line {line}: invalid {field} '{value}', {reason}"),
+            Self::DuplicateId { line, id } => write!(f, "line {line}: duplicate id {id}"),
+            Self::UnknownFormat(path) => write!(
+                f,
+                "unknown catalog format for '{path}', expected .csv or .json"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
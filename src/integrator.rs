@@ -0,0 +1,277 @@
+use std::{str::FromStr, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{body::Body, float::Float, simulation::Simulation, vector::Vector};
+
+// Deepest level allowed for `BlockLeapfrog`, whose steps then take a million substeps
+pub const MAX_LEVEL: u32 = 20;
+
+// What the integrators need from a simulation, implemented by `Simulation` and `Simulation3`
+pub trait System {
+    type Vector: Vector;
+
+    fn dt(&self) -> Float;
+
+    // Global softening length, which bounds the timestep criterion of `BlockLeapfrog`
+    fn epsilon(&self) -> Float;
+
+    fn bodies(&self) -> &[Body<Self::Vector>];
+
+    fn bodies_mut(&mut self) -> &mut [Body<Self::Vector>];
+
+    // Rebuilds the forces from all bodies but only updates the accelerations of those matching `active`
+    fn attract_where(&mut self, active: &(dyn Fn(&Body<Self::Vector>) -> bool + Sync));
+
+    fn collide(&mut self);
+
+    // Brings positions back into the domain after they move, for periodic boundaries
+    fn wrap(&mut self) {}
+
+    fn attract(&mut self) {
+        self.attract_where(&|_| true);
+    }
+
+    fn iterate(&mut self) {
+        let dt = self.dt();
+        for body in self.bodies_mut() {
+            body.update(dt);
+        }
+        self.wrap();
+    }
+
+    fn kick(&mut self, dt: Float) {
+        for body in self.bodies_mut() {
+            body.kick(dt);
+        }
+    }
+
+    fn drift(&mut self, dt: Float) {
+        for body in self.bodies_mut() {
+            body.drift(dt);
+        }
+        self.wrap();
+    }
+}
+
+// Advances a simulation by one step of `System::dt`
+pub trait Integrator<S: System = Simulation>: Send + Sync {
+    // Called before the first step, for schemes that need accelerations up front
+    fn start(&self, _simulation: &mut S) {}
+
+    fn step(&self, simulation: &mut S);
+}
+
+// The original scheme: kick with the last accelerations, drift, then recompute accelerations
+pub struct SemiImplicitEuler;
+
+impl<S: System> Integrator<S> for SemiImplicitEuler {
+    fn step(&self, simulation: &mut S) {
+        simulation.iterate();
+        simulation.collide();
+        simulation.attract();
+    }
+}
+
+// Kick-drift-kick leapfrog, symplectic and second order
+pub struct Leapfrog;
+
+impl<S: System> Integrator<S> for Leapfrog {
+    fn start(&self, simulation: &mut S) {
+        simulation.attract();
+    }
+
+    fn step(&self, simulation: &mut S) {
+        let dt = simulation.dt();
+        simulation.kick(0.5 * dt);
+        simulation.drift(dt);
+        simulation.collide();
+        simulation.attract();
+        simulation.kick(0.5 * dt);
+    }
+}
+
+// Velocity Verlet, which updates velocities with the average of the old and new accelerations
+pub struct VelocityVerlet;
+
+impl<S: System> Integrator<S> for VelocityVerlet {
+    fn start(&self, simulation: &mut S) {
+        simulation.attract();
+    }
+
+    fn step(&self, simulation: &mut S) {
+        let dt = simulation.dt();
+        for body in simulation.bodies_mut() {
+            body.pos += body.vel * dt + body.acc * (0.5 * dt * dt);
+        }
+        simulation.wrap();
+
+        simulation.collide();
+
+        let old: Vec<S::Vector> = simulation.bodies().iter().map(|body| body.acc).collect();
+        simulation.attract();
+
+        for (body, old) in simulation.bodies_mut().iter_mut().zip(old) {
+            body.vel += (old + body.acc) * (0.5 * dt);
+        }
+    }
+}
+
+// Kick-drift-kick leapfrog with power-of-two block timesteps. `System::dt` is the largest
+// timestep, and each body steps with `dt / 2^level` so that only the bodies ending a step at a
+// given substep get their forces recomputed.
+pub struct BlockLeapfrog {
+    pub max_level: u32,
+    pub eta: Float,
+}
+
+impl BlockLeapfrog {
+    // Number of finest substeps in a step at `level`
+    fn span(&self, level: u32) -> u32 {
+        1 << (self.max_level - level)
+    }
+
+    // Picks the level whose timestep satisfies `dt_i < sqrt(2 eta epsilon / |a|)`, with `epsilon`
+    // the body's own softening length if it is larger than the global one. Unsoftened bodies use
+    // their radius instead, and points the distance `|a| dt²` they would fall in a whole step.
+    fn ideal_level<V: Vector>(&self, body: &Body<V>, dt: Float, epsilon: Float) -> u32 {
+        let acc = body.acc.mag();
+        if acc == 0.0 {
+            return 0;
+        }
+
+        let mut length = epsilon.max(body.softening);
+        if length == 0.0 {
+            length = body.radius;
+        }
+        if length == 0.0 {
+            length = acc * dt * dt;
+        }
+
+        let ideal = (2.0 * self.eta * length / acc).sqrt();
+        let level = (dt / ideal).log2().ceil().max(0.0) as u32;
+        level.min(self.max_level)
+    }
+
+    // Bodies can always move to a smaller timestep, but only to a larger one if the current
+    // substep lines up with the start of a step of that size
+    fn next_level<V: Vector>(&self, body: &Body<V>, tick: u32, dt: Float, epsilon: Float) -> u32 {
+        let mut level = self.ideal_level(body, dt, epsilon);
+        while level < body.level && !tick.is_multiple_of(self.span(level)) {
+            level += 1;
+        }
+        level
+    }
+}
+
+impl<S: System> Integrator<S> for BlockLeapfrog {
+    fn start(&self, simulation: &mut S) {
+        simulation.attract();
+
+        let dt = simulation.dt();
+        let epsilon = simulation.epsilon();
+        for body in simulation.bodies_mut() {
+            body.level = self.ideal_level(body, dt, epsilon);
+        }
+    }
+
+    fn step(&self, simulation: &mut S) {
+        let dt = simulation.dt();
+        let epsilon = simulation.epsilon();
+        let ticks = self.span(0);
+        let tick_dt = dt / ticks as Float;
+        let level_dt = |level: u32| dt / (1 << level) as Float;
+
+        for body in simulation.bodies_mut() {
+            body.level = body.level.min(self.max_level);
+        }
+
+        for tick in 0..ticks {
+            for body in simulation.bodies_mut() {
+                if tick.is_multiple_of(self.span(body.level)) {
+                    body.kick(0.5 * level_dt(body.level));
+                }
+            }
+
+            simulation.drift(tick_dt);
+
+            let tick = tick + 1;
+            let active = |body: &Body<S::Vector>| tick.is_multiple_of(self.span(body.level));
+            if !simulation.bodies().iter().any(active) {
+                continue;
+            }
+
+            // Everything is synchronized at the end of the step
+            if tick == ticks {
+                simulation.collide();
+            }
+
+            simulation.attract_where(&active);
+
+            for body in simulation
+                .bodies_mut()
+                .iter_mut()
+                .filter(|body| active(body))
+            {
+                body.kick(0.5 * level_dt(body.level));
+                body.level = self.next_level(body, tick, dt, epsilon);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimestepConfig {
+    // The smallest timestep is `dt / 2^max_level`, with `max_level` at most `MAX_LEVEL`
+    pub max_level: u32,
+    pub eta: Float,
+}
+
+impl Default for TimestepConfig {
+    fn default() -> Self {
+        Self {
+            max_level: 6,
+            eta: 0.025,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IntegratorKind {
+    #[default]
+    SemiImplicitEuler,
+    Leapfrog,
+    VelocityVerlet,
+    BlockLeapfrog,
+}
+
+impl IntegratorKind {
+    // Expects `timesteps` from a config that passed `SimulationConfig::validate`
+    pub fn build<S: System>(self, timesteps: TimestepConfig) -> Arc<dyn Integrator<S>> {
+        match self {
+            Self::SemiImplicitEuler => Arc::new(SemiImplicitEuler),
+            Self::Leapfrog => Arc::new(Leapfrog),
+            Self::VelocityVerlet => Arc::new(VelocityVerlet),
+            Self::BlockLeapfrog => Arc::new(BlockLeapfrog {
+                max_level: timesteps.max_level,
+                eta: timesteps.eta,
+            }),
+        }
+    }
+}
+
+impl FromStr for IntegratorKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "euler" | "semi_implicit_euler" => Ok(Self::SemiImplicitEuler),
+            "leapfrog" | "kdk" => Ok(Self::Leapfrog),
+            "verlet" | "velocity_verlet" => Ok(Self::VelocityVerlet),
+            "block" | "block_leapfrog" => Ok(Self::BlockLeapfrog),
+            _ => Err(format!("unknown integrator '{s}'")),
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    body::Body,
+    float::{Float, Vec2},
+    quadtree::Quadtree,
+};
+
+// Support of the spline kernel in units of the softening length, which makes its potential depth
+// at `d = 0` match that of a Plummer sphere with the same `ε`
+const SPLINE_SUPPORT: Float = 2.8;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Softening {
+    // Potential `-m / sqrt(d² + ε²)`
+    #[default]
+    Plummer,
+    // Monaghan cubic spline, exactly Newtonian beyond `2.8 ε`
+    Spline,
+    // Newtonian, `ε` is ignored
+    None,
+}
+
+impl Softening {
+    // Acceleration towards a mass at offset `d`, zero at `d = 0` so that bodies don't attract themselves
+    #[inline]
+    pub fn acc(self, d: Vec2, d_sq: Float, mass: Float, epsilon: Float) -> Vec2 {
+        d * self.factor(d_sq, mass, epsilon)
+    }
+
+    // `|a| / d`, so that the acceleration towards a mass at offset `d` is `d * factor` in any dimension
+    #[inline]
+    pub fn factor(self, d_sq: Float, mass: Float, epsilon: Float) -> Float {
+        if d_sq == 0.0 {
+            return 0.0;
+        }
+
+        match self {
+            Self::Plummer => {
+                let r_sq = d_sq + epsilon * epsilon;
+                mass / (r_sq * r_sq.sqrt())
+            }
+            Self::Spline if d_sq < (SPLINE_SUPPORT * epsilon).powi(2) => {
+                spline_acc(d_sq.sqrt(), mass, SPLINE_SUPPORT * epsilon)
+            }
+            Self::Spline | Self::None => mass / (d_sq * d_sq.sqrt()),
+        }
+    }
+
+    // Potential of the force law in `acc`, zero at `d = 0` so that bodies don't count themselves
+    #[inline]
+    pub fn potential(self, d_sq: Float, mass: Float, epsilon: Float) -> Float {
+        if d_sq == 0.0 {
+            return 0.0;
+        }
+
+        match self {
+            Self::Plummer => -mass / (d_sq + epsilon * epsilon).sqrt(),
+            Self::Spline if d_sq < (SPLINE_SUPPORT * epsilon).powi(2) => {
+                spline_potential(d_sq.sqrt(), mass, SPLINE_SUPPORT * epsilon)
+            }
+            Self::Spline | Self::None => -mass / d_sq.sqrt(),
+        }
+    }
+
+    // Softening for the quadrupole correction of distant nodes. The spline is already Newtonian
+    // at the distances where nodes are accepted, so only Plummer keeps it.
+    #[inline]
+    pub fn quadrupole_e_sq(self, epsilon: Float) -> Float {
+        match self {
+            Self::Plummer => epsilon * epsilon,
+            Self::Spline | Self::None => 0.0,
+        }
+    }
+}
+
+impl FromStr for Softening {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plummer" => Ok(Self::Plummer),
+            "spline" => Ok(Self::Spline),
+            "none" => Ok(Self::None),
+            _ => Err(format!("unknown softening kernel '{s}'")),
+        }
+    }
+}
+
+// Coefficients from Springel, Yoshida & White (2001), with `u = d / h`
+fn spline_acc(d: Float, mass: Float, h: Float) -> Float {
+    let u = d / h;
+    let w = if u < 0.5 {
+        10.666667 + u * u * (32.0 * u - 38.4)
+    } else {
+        21.333334 - 48.0 * u + 38.4 * u * u - 10.666667 * u * u * u - 0.06666667 / (u * u * u)
+    };
+    mass * w / (h * h * h)
+}
+
+fn spline_potential(d: Float, mass: Float, h: Float) -> Float {
+    let u = d / h;
+    let w = if u < 0.5 {
+        -2.8 + u * u * (5.3333335 + u * u * (6.4 * u - 9.6))
+    } else {
+        -3.2 + 0.06666667 / u + u * u * (10.666667 + u * (-16.0 + u * (9.6 - 2.1333334 * u)))
+    };
+    mass * w / h
+}
+
+// How `Body::softening` is set before every force evaluation. The softening between two bodies is
+// the largest of their own lengths and the global `epsilon`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdaptiveSoftening {
+    // Left as it is, zero unless set by hand
+    #[default]
+    Fixed,
+    // `scale * radius`
+    Radius,
+    // `scale` times the mean spacing between bodies in the same quadtree leaf
+    Density,
+}
+
+impl AdaptiveSoftening {
+    // Bodies per leaf of the tree used to estimate the local density
+    const DENSITY_NEIGHBOURS: usize = 8;
+
+    pub fn apply(self, bodies: &mut [Body], scale: Float, threads: usize) {
+        match self {
+            Self::Fixed => {}
+            Self::Radius => {
+                for body in bodies {
+                    body.softening = scale * body.radius;
+                }
+            }
+            Self::Density => {
+                let mut quadtree = Quadtree::new(0.0, 0.0);
+                quadtree.leaf_capacity = Self::DENSITY_NEIGHBOURS;
+                quadtree.build_morton_parallel(bodies, threads);
+
+                for n in quadtree
+                    .nodes
+                    .iter()
+                    .filter(|n| n.is_leaf() && !n.is_empty())
+                {
+                    // Area per unit mass, so that heavier bodies get proportionally more room
+                    let area = n.quad.size * n.quad.size / n.mass;
+                    for p in &quadtree.points[n.bodies.clone()] {
+                        bodies[p.index].softening = scale * (p.mass * area).sqrt();
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for AdaptiveSoftening {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fixed" => Ok(Self::Fixed),
+            "radius" => Ok(Self::Radius),
+            "density" => Ok(Self::Density),
+            _ => Err(format!("unknown adaptive softening '{s}'")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::float::widen;
+
+    fn relative_error(a: Float, b: Float) -> f64 {
+        ((widen(a) - widen(b)) / widen(b)).abs()
+    }
+
+    #[test]
+    fn spline_is_newtonian_beyond_its_support() {
+        let (mass, epsilon): (Float, Float) = (3.0, 2.0);
+        let h = SPLINE_SUPPORT * epsilon;
+
+        for d in [h, 1.5 * h, 10.0 * h] {
+            let d_sq = d * d;
+            let spline = Softening::Spline;
+            let newton = Softening::None;
+            assert_eq!(
+                spline.factor(d_sq, mass, epsilon),
+                newton.factor(d_sq, mass, epsilon)
+            );
+            assert_eq!(
+                spline.potential(d_sq, mass, epsilon),
+                newton.potential(d_sq, mass, epsilon)
+            );
+        }
+
+        // And joins it continuously from the inside
+        let d_sq = (0.999 * h).powi(2);
+        let factor = Softening::Spline.factor(d_sq, mass, epsilon);
+        assert!(relative_error(factor, Softening::None.factor(d_sq, mass, epsilon)) < 1e-4);
+        let potential = Softening::Spline.potential(d_sq, mass, epsilon);
+        assert!(relative_error(potential, Softening::None.potential(d_sq, mass, epsilon)) < 1e-4);
+    }
+
+    #[test]
+    fn spline_matches_plummer_at_the_center() {
+        let (mass, epsilon): (Float, Float) = (3.0, 2.0);
+        let d_sq = (1e-3 * epsilon).powi(2);
+        let spline = Softening::Spline.potential(d_sq, mass, epsilon);
+        let plummer = Softening::Plummer.potential(d_sq, mass, epsilon);
+        assert!(
+            relative_error(spline, plummer) < 1e-3,
+            "{spline} vs {plummer}"
+        );
+    }
+
+    #[test]
+    fn spline_acceleration_is_the_gradient_of_its_potential() {
+        let (mass, epsilon): (Float, Float) = (3.0, 2.0);
+        let h = SPLINE_SUPPORT * epsilon;
+        let step = 1e-3 * h;
+
+        for u in [0.1, 0.3, 0.49, 0.51, 0.7, 0.9] {
+            let d = u * h;
+            let potential = |d: Float| widen(Softening::Spline.potential(d * d, mass, epsilon));
+            let gradient = (potential(d + step) - potential(d - step)) / widen(2.0 * step);
+            let acc = widen(Softening::Spline.factor(d * d, mass, epsilon) * d);
+            assert!(
+                ((gradient - acc) / acc).abs() < 1e-2,
+                "u = {u}: {acc} vs {gradient}"
+            );
+        }
+    }
+}
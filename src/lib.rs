@@ -0,0 +1,46 @@
+pub mod body;
+pub mod config;
+pub mod diagnostics;
+pub mod direct;
+pub mod export;
+pub mod float;
+pub mod fmm;
+pub mod import;
+pub mod integrator;
+pub mod kernel;
+pub mod models;
+pub mod octree;
+pub mod parallel;
+pub mod periodic;
+pub mod projection;
+pub mod quadtree;
+pub mod scenario;
+pub mod simulation;
+pub mod simulation3;
+pub mod snapshot;
+pub mod solver;
+pub mod utils;
+pub mod vector;
+
+pub use body::{Body, Body3};
+pub use config::{
+    CollisionConfig, ConfigError, DiscConfig, InitialConditions, ModelConfig, PeriodicConfig,
+    SimulationConfig,
+};
+pub use diagnostics::{Diagnostics, DiagnosticsWriter};
+pub use export::{Format, TrajectoryWriter};
+pub use float::Float;
+pub use fmm::{Fmm, FmmConfig};
+pub use import::{Catalog, ImportError};
+pub use integrator::{Integrator, IntegratorKind, System, TimestepConfig};
+pub use kernel::{AdaptiveSoftening, Softening};
+pub use octree::{Cube, Octree, OctreeNode};
+pub use periodic::Periodic;
+pub use projection::{Projection, ProjectionKind};
+pub use quadtree::{Construction, Multipole, Node, Opening, Quad, Quadtree};
+pub use scenario::{Component, Scenario, Spin};
+pub use simulation::Simulation;
+pub use simulation3::Simulation3;
+pub use snapshot::{Snapshot, SnapshotError};
+pub use solver::{BarnesHut, GravitySolver, SolverKind};
+pub use vector::Vector;
@@ -1,22 +1,71 @@
 use std::sync::atomic::Ordering;
 
-mod body;
-mod quadtree;
 mod renderer;
-mod simulation;
-mod utils;
 
+use barnes_hut::{
+    float::{Vec2, Vec3},
+    Body, Body3, Catalog, CollisionConfig, Float, InitialConditions, Simulation, Simulation3,
+    SimulationConfig,
+};
 use renderer::Renderer;
-use simulation::Simulation;
 
 fn main() {
     let config = quarkstrom::Config {
         window_mode: quarkstrom::WindowMode::Windowed(900, 900),
     };
 
-    let mut simulation = Simulation::new();
+    if std::env::args().any(|arg| arg == "--3d") {
+        let mut simulation = SimulationConfig::default()
+            .dimensions(3)
+            .initial_conditions(InitialConditions::UniformSphere)
+            .collisions(CollisionConfig {
+                enabled: false,
+                ..CollisionConfig::default()
+            })
+            .build3()
+            .expect("the 3D config is valid");
 
-    std::thread::spawn(move || {
+        let radius = (simulation.bodies.len() as Float).cbrt() * 5.0;
+        renderer::PROJECTION.lock().distance = 4.0 * radius;
+        renderer::THREE_D.store(true, Ordering::Relaxed);
+
+        std::thread::spawn(move || loop {
+            if renderer::PAUSED.load(Ordering::Relaxed) {
+                std::thread::yield_now();
+            } else {
+                simulation.step();
+            }
+            render3(&mut simulation);
+        });
+    } else {
+        let args = std::env::args().collect::<Vec<_>>();
+        let mut simulation = match args.iter().position(|arg| arg == "--load") {
+            Some(i) => {
+                let path = args.get(i + 1).map(String::as_str).unwrap_or(SAVE_PATH);
+                Simulation::load(path).unwrap_or_else(|e| {
+                    eprintln!("error: {e}");
+                    std::process::exit(1);
+                })
+            }
+            None => match args.iter().position(|arg| arg == "--import") {
+                Some(i) => {
+                    let Some(path) = args.get(i + 1) else {
+                        eprintln!("error: missing catalog for '--import'");
+                        std::process::exit(1);
+                    };
+                    let catalog = Catalog::load(path).unwrap_or_else(|e| {
+                        eprintln!("error: {e}");
+                        std::process::exit(1);
+                    });
+                    SimulationConfig::default()
+                        .build_with(catalog.bodies)
+                        .expect("the default config is valid")
+                }
+                None => Simulation::new(),
+            },
+        };
+
+        std::thread::spawn(move || {
 	    loop {
 	        if renderer::PAUSED.load(Ordering::Relaxed) {
 	            std::thread::yield_now();
@@ -25,13 +74,23 @@ fn main() {
 	        }
 	        render(&mut simulation);
 	    }
-    });
+        });
+    }
 
     quarkstrom::run::<Renderer>(config);
 }
 
+// Where the viewer saves to, and loads from with `--load` if no other path is given
+const SAVE_PATH: &str = "snapshot.bin";
+
 fn render(simulation: &mut Simulation) {
     let mut lock = renderer::UPDATE_LOCK.lock();
+    if renderer::SAVE.swap(false, Ordering::Relaxed) {
+        match simulation.save(SAVE_PATH) {
+            Ok(()) => println!("saved frame {} to {SAVE_PATH}", simulation.frame),
+            Err(e) => eprintln!("error: {e}"),
+        }
+    }
     for body in renderer::SPAWN.lock().drain(..) {
         simulation.bodies.push(body);
     }
@@ -43,7 +102,34 @@ fn render(simulation: &mut Simulation) {
     {
         let mut lock = renderer::QUADTREE.lock();
         lock.clear();
-        lock.extend_from_slice(&simulation.quadtree.nodes);
+        lock.extend_from_slice(simulation.solver.nodes());
+    }
+    *lock |= true;
+}
+
+// Draws the bodies through the current projection, with lengths scaled by their depth
+fn render3(simulation: &mut Simulation3) {
+    let mut lock = renderer::UPDATE_LOCK.lock();
+    let projection = *renderer::PROJECTION.lock();
+    let rotation = projection.rotation();
+
+    // Spawned bodies are placed on the view plane through the origin
+    let inverse = rotation.transposed();
+    for body in renderer::SPAWN.lock().drain(..) {
+        let pos = inverse * Vec3::new(body.pos.x, body.pos.y, 0.0);
+        let vel = inverse * Vec3::new(body.vel.x, body.vel.y, 0.0);
+        simulation
+            .bodies
+            .push(Body3::new(pos, vel, body.mass, body.radius));
+    }
+    {
+        let mut lock = renderer::BODIES.lock();
+        lock.clear();
+        lock.extend(simulation.bodies.iter().filter_map(|body| {
+            let (pos, scale) = projection.project_rotated(rotation * body.pos)?;
+            Some(Body::new(pos, Vec2::zero(), body.mass, body.radius * scale))
+        }));
     }
+    renderer::QUADTREE.lock().clear();
     *lock |= true;
 }
@@ -0,0 +1,386 @@
+use std::f64::consts::{PI, TAU};
+
+use ultraviolet::DVec3;
+
+use crate::{
+    body::{Body, Body3},
+    float::{self, widen, Float, Vec2, Vec3},
+    periodic::erf,
+    quadtree::Quadtree,
+};
+
+// Points of the geometric grid used to find the peak of the speed distribution, which moves
+// towards zero near the center of cuspy models
+const SPEED_GRID: i32 = 160;
+
+// Step in `ln r` when integrating the King model
+const KING_STEP: f64 = 0.005;
+
+// Spherical equilibrium model with an isotropic distribution function, in units where the
+// gravitational constant, the total mass and the scale radius are 1
+trait Model {
+    // Radius enclosing the mass fraction `x`
+    fn radius(&self, x: f64) -> f64;
+
+    // Relative potential `ψ = -Φ` at radius `r`, in the velocity units of `df`
+    fn psi(&self, r: f64) -> f64;
+
+    // Distribution function of the relative energy `ε = ψ - v² / 2`, up to a constant
+    fn df(&self, e: f64) -> f64;
+
+    // Mass fraction that gets sampled, models of infinite extent are truncated
+    fn truncation(&self) -> f64 {
+        1.0
+    }
+
+    // Converts speeds from the units of `psi` into units of the model
+    fn velocity_scale(&self) -> f64 {
+        1.0
+    }
+}
+
+struct Plummer;
+
+impl Model for Plummer {
+    fn radius(&self, x: f64) -> f64 {
+        (x.powf(-2.0 / 3.0) - 1.0).sqrt().recip()
+    }
+
+    fn psi(&self, r: f64) -> f64 {
+        (1.0 + r * r).sqrt().recip()
+    }
+
+    fn df(&self, e: f64) -> f64 {
+        e.max(0.0).powf(3.5)
+    }
+
+    // Out to about 39 scale radii
+    fn truncation(&self) -> f64 {
+        0.999
+    }
+}
+
+struct Hernquist;
+
+impl Model for Hernquist {
+    fn radius(&self, x: f64) -> f64 {
+        let s = x.sqrt();
+        s / (1.0 - s)
+    }
+
+    fn psi(&self, r: f64) -> f64 {
+        (1.0 + r).recip()
+    }
+
+    // Hernquist (1990), eq. 17
+    fn df(&self, e: f64) -> f64 {
+        if e <= 0.0 {
+            return 0.0;
+        }
+
+        let q = e.min(1.0).sqrt();
+        let q_sq = q * q;
+        let bracket = 3.0 * q.asin()
+            + q * (1.0 - q_sq).sqrt() * (1.0 - 2.0 * q_sq) * (8.0 * q_sq * q_sq - 8.0 * q_sq - 3.0);
+        bracket / (1.0 - q_sq).powf(2.5)
+    }
+
+    // Out to about 200 scale radii, the density only falls off as `r⁻⁴`
+    fn truncation(&self) -> f64 {
+        0.99
+    }
+}
+
+// Lowered isothermal sphere with central potential `W₀ = ψ(0) / σ²`, tabulated from the
+// Poisson equation in units of the King radius `r₀ = √(9σ² / 4πGρ₀)` and `σ = 1`
+struct King {
+    // `(r, W, mass fraction within r)` from the center out to the tidal radius
+    table: Vec<(f64, f64, f64)>,
+    mass: f64,
+}
+
+impl King {
+    fn new(w0: f64) -> Self {
+        assert!(w0 > 0.0, "King models need a positive W0");
+
+        let density = |w: f64| {
+            if w <= 0.0 {
+                return 0.0;
+            }
+            let rho =
+                |w: f64| w.exp() * erf(w.sqrt()) - (4.0 * w / PI).sqrt() * (1.0 + 2.0 * w / 3.0);
+            (rho(w) / rho(w0)).max(0.0)
+        };
+
+        // `(W, dW/dr)` as functions of `s = ln r`
+        let derivative = |s: f64, (w, p): (f64, f64)| {
+            let r = s.exp();
+            (r * p, -9.0 * r * density(w) - 2.0 * p)
+        };
+
+        // Series solution `W ≈ W₀ - 3r² / 2` to start away from the singular center
+        let mut r: f64 = 1e-4;
+        let mut state = (w0 - 1.5 * r * r, -3.0 * r);
+        let mut table = vec![(0.0, w0, 0.0), (r, state.0, -r * r * state.1)];
+
+        while state.0 > 0.0 {
+            let s = r.ln();
+            let h = KING_STEP;
+            let add = |(w, p): (f64, f64), (dw, dp): (f64, f64), k: f64| (w + dw * k, p + dp * k);
+
+            let k1 = derivative(s, state);
+            let k2 = derivative(s + 0.5 * h, add(state, k1, 0.5 * h));
+            let k3 = derivative(s + 0.5 * h, add(state, k2, 0.5 * h));
+            let k4 = derivative(s + h, add(state, k3, h));
+            let next = (
+                state.0 + h / 6.0 * (k1.0 + 2.0 * k2.0 + 2.0 * k3.0 + k4.0),
+                state.1 + h / 6.0 * (k1.1 + 2.0 * k2.1 + 2.0 * k3.1 + k4.1),
+            );
+            let next_r = (s + h).exp();
+
+            if next.0 <= 0.0 {
+                // End at the tidal radius, where `W` reaches zero
+                let t = state.0 / (state.0 - next.0);
+                let tidal = r + t * (next_r - r);
+                let p = state.1 + t * (next.1 - state.1);
+                table.push((tidal, 0.0, -tidal * tidal * p));
+                break;
+            }
+
+            r = next_r;
+            state = next;
+            table.push((r, state.0, -r * r * state.1));
+        }
+
+        // `M(r) = -r² dW/dr` in these units
+        let mass = table.last().unwrap().2;
+        for entry in &mut table {
+            entry.2 /= mass;
+        }
+
+        Self { table, mass }
+    }
+
+    fn tidal_radius(&self) -> f64 {
+        self.table.last().unwrap().0
+    }
+}
+
+impl Model for King {
+    fn radius(&self, x: f64) -> f64 {
+        let i = self.table.partition_point(|entry| entry.2 < x).max(1);
+        let (r0, _, m0) = self.table[i - 1];
+        let (r1, _, m1) = self.table[i.min(self.table.len() - 1)];
+        if m1 <= m0 {
+            return r1;
+        }
+        r0 + (x - m0) / (m1 - m0) * (r1 - r0)
+    }
+
+    fn psi(&self, r: f64) -> f64 {
+        if r >= self.tidal_radius() {
+            return 0.0;
+        }
+
+        let i = self.table.partition_point(|entry| entry.0 < r).max(1);
+        let (r0, w0, _) = self.table[i - 1];
+        let (r1, w1, _) = self.table[i];
+        w0 + (r - r0) / (r1 - r0) * (w1 - w0)
+    }
+
+    fn df(&self, e: f64) -> f64 {
+        e.max(0.0).exp_m1()
+    }
+
+    // Scaled from `σ = 1` to unit total mass
+    fn velocity_scale(&self) -> f64 {
+        self.mass.sqrt().recip()
+    }
+}
+
+// Plummer sphere, whose velocities are drawn from its distribution function the same way as the
+// rejection method of Aarseth, Hénon & Wielen (1974)
+pub fn plummer3(n: usize, mass: Float, scale_radius: Float, seed: u64) -> Vec<Body3> {
+    sample(&Plummer, n, mass, scale_radius, seed)
+}
+
+// Hernquist (1990) model, with the density `ρ ∝ 1 / r (r + a)³` of galactic bulges and halos
+pub fn hernquist3(n: usize, mass: Float, scale_radius: Float, seed: u64) -> Vec<Body3> {
+    sample(&Hernquist, n, mass, scale_radius, seed)
+}
+
+// King (1966) model with central potential `w0` in units of `σ²`, from about 1 for a diffuse
+// cluster to 12 for a concentrated one. `scale_radius` is the King radius, and the model ends at
+// the tidal radius where the potential reaches zero.
+pub fn king3(n: usize, mass: Float, scale_radius: Float, w0: Float, seed: u64) -> Vec<Body3> {
+    sample(&King::new(widen(w0)), n, mass, scale_radius, seed)
+}
+
+// 2D analogues of the models above: the sphere projected onto the plane, with the in-plane part
+// of each velocity. A flattened sphere isn't in equilibrium under the 3D force law, so the
+// velocities are then scaled to put the whole system in virial equilibrium.
+pub fn plummer(n: usize, mass: Float, scale_radius: Float, seed: u64) -> Vec<Body> {
+    flatten(plummer3(n, mass, scale_radius, seed))
+}
+
+pub fn hernquist(n: usize, mass: Float, scale_radius: Float, seed: u64) -> Vec<Body> {
+    flatten(hernquist3(n, mass, scale_radius, seed))
+}
+
+pub fn king(n: usize, mass: Float, scale_radius: Float, w0: Float, seed: u64) -> Vec<Body> {
+    flatten(king3(n, mass, scale_radius, w0, seed))
+}
+
+// Positions from the cumulative mass profile, speeds by rejection from `v² f(ψ - v² / 2)`, and
+// isotropic directions for both
+fn sample(model: &impl Model, n: usize, mass: Float, scale_radius: Float, seed: u64) -> Vec<Body3> {
+    fastrand::seed(seed);
+    let body_mass = mass / n.max(1) as Float;
+    let length = widen(scale_radius);
+    let velocity = (widen(mass) / length).sqrt() * model.velocity_scale();
+
+    let mut positions = Vec::with_capacity(n);
+    let mut velocities = Vec::with_capacity(n);
+    for _ in 0..n {
+        let r = model.radius(random() * model.truncation());
+        let v = speed(model, model.psi(r));
+        positions.push(direction() * r * length);
+        velocities.push(direction() * v * velocity);
+    }
+
+    // Place the center of mass at rest at the origin
+    let count = n.max(1) as f64;
+    let center = positions.iter().fold(DVec3::zero(), |sum, &p| sum + p) / count;
+    let drift = velocities.iter().fold(DVec3::zero(), |sum, &v| sum + v) / count;
+
+    let narrow = |v: DVec3| Vec3::new(v.x as Float, v.y as Float, v.z as Float);
+    positions
+        .into_iter()
+        .zip(velocities)
+        .map(|(pos, vel)| {
+            Body3::new(
+                narrow(pos - center),
+                narrow(vel - drift),
+                body_mass,
+                body_mass.cbrt(),
+            )
+        })
+        .collect()
+}
+
+fn speed(model: &impl Model, psi: f64) -> f64 {
+    if psi <= 0.0 {
+        return 0.0;
+    }
+
+    let v_max = (2.0 * psi).sqrt();
+    let density = |v: f64| v * v * model.df(psi - 0.5 * v * v);
+
+    let peak = (0..SPEED_GRID)
+        .map(|k| density(v_max * 0.92f64.powi(k)))
+        .fold(0.0, f64::max);
+    if peak <= 0.0 {
+        return 0.0;
+    }
+
+    // The margin covers the peak falling between grid points
+    let bound = 1.2 * peak;
+    loop {
+        let v = v_max * random();
+        if random() * bound < density(v) {
+            return v;
+        }
+    }
+}
+
+fn flatten(bodies: Vec<Body3>) -> Vec<Body> {
+    let mut bodies = bodies
+        .into_iter()
+        .map(|body| {
+            let pos = Vec2::new(body.pos.x, body.pos.y);
+            let vel = Vec2::new(body.vel.x, body.vel.y);
+            Body::new(pos, vel, body.mass, body.radius)
+        })
+        .collect::<Vec<_>>();
+
+    let mut quadtree = Quadtree::new(0.7, 0.0);
+    quadtree.build(&bodies);
+
+    let mut kinetic = 0.0;
+    let mut potential = 0.0;
+    for body in &bodies {
+        kinetic += 0.5 * widen(body.mass) * widen(body.vel.mag_sq());
+        potential += 0.5 * widen(body.mass) * widen(quadtree.potential_of(body));
+    }
+
+    if kinetic > 0.0 {
+        let scale = (potential.abs() / (2.0 * kinetic)).sqrt() as Float;
+        for body in &mut bodies {
+            body.vel *= scale;
+        }
+    }
+
+    bodies
+}
+
+fn random() -> f64 {
+    widen(float::random())
+}
+
+fn direction() -> DVec3 {
+    let z = 2.0 * random() - 1.0;
+    let (sin, cos) = (TAU * random()).sin_cos();
+    let rho = (1.0 - z * z).sqrt();
+    DVec3::new(rho * cos, rho * sin, z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vector::Vector;
+
+    // `2 K / |W|`, with the potential energy summed directly over every pair
+    fn virial_ratio<V: Vector>(bodies: &[Body<V>]) -> f64 {
+        let mut kinetic = 0.0;
+        let mut potential = 0.0;
+        for (i, a) in bodies.iter().enumerate() {
+            kinetic += 0.5 * widen(a.mass) * a.vel.to_dvec3().mag_sq();
+            for b in &bodies[i + 1..] {
+                let d = (b.pos.to_dvec3() - a.pos.to_dvec3()).mag();
+                potential -= widen(a.mass) * widen(b.mass) / d;
+            }
+        }
+        2.0 * kinetic / potential.abs()
+    }
+
+    // Sampled from their distribution functions, the 3D models are in equilibrium up to the noise
+    // of a finite sample, and the flattened ones are scaled to it using a tree potential
+    #[test]
+    fn models_are_in_virial_equilibrium() {
+        let (n, mass, radius, seed) = (2000, 100000.0, 300.0, 1);
+        let models = [
+            (
+                "plummer",
+                plummer3(n, mass, radius, seed),
+                plummer(n, mass, radius, seed),
+            ),
+            (
+                "hernquist",
+                hernquist3(n, mass, radius, seed),
+                hernquist(n, mass, radius, seed),
+            ),
+            (
+                "king",
+                king3(n, mass, radius, 6.0, seed),
+                king(n, mass, radius, 6.0, seed),
+            ),
+        ];
+
+        for (name, bodies3, bodies) in models {
+            let ratio = virial_ratio(&bodies3);
+            assert!((ratio - 1.0).abs() < 0.05, "{name} in 3D: {ratio}");
+            let ratio = virial_ratio(&bodies);
+            assert!((ratio - 1.0).abs() < 0.02, "{name} in 2D: {ratio}");
+        }
+    }
+}
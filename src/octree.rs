@@ -0,0 +1,339 @@
+use std::ops::Range;
+
+use crate::{
+    body::Body3,
+    float::{Float, Vec3},
+    kernel::Softening,
+    quadtree::partition,
+};
+
+// 3D counterpart of `Quad`
+#[derive(Clone, Copy)]
+pub struct Cube {
+    pub center: Vec3,
+    pub size: Float,
+}
+
+impl Cube {
+    pub fn new_containing(bodies: &[Body3]) -> Self {
+        let mut min = Vec3::broadcast(Float::MAX);
+        let mut max = Vec3::broadcast(Float::MIN);
+
+        for body in bodies {
+            min = min.min_by_component(body.pos);
+            max = max.max_by_component(body.pos);
+        }
+
+        let center = (min + max) * 0.5;
+        let size = (max - min).component_max();
+
+        Self { center, size }
+    }
+
+    pub fn find_octant(&self, pos: Vec3) -> usize {
+        ((pos.z > self.center.z) as usize) << 2
+            | ((pos.y > self.center.y) as usize) << 1
+            | (pos.x > self.center.x) as usize
+    }
+
+    pub fn into_octant(mut self, octant: usize) -> Self {
+        self.size *= 0.5;
+        self.center.x += ((octant & 1) as Float - 0.5) * self.size;
+        self.center.y += ((octant >> 1 & 1) as Float - 0.5) * self.size;
+        self.center.z += ((octant >> 2) as Float - 0.5) * self.size;
+        self
+    }
+
+    pub fn subdivide(&self) -> [Cube; 8] {
+        [0, 1, 2, 3, 4, 5, 6, 7].map(|i| self.into_octant(i))
+    }
+}
+
+#[derive(Clone)]
+pub struct OctreeNode {
+    pub children: usize,
+    pub next: usize,
+    pub pos: Vec3,
+    pub mass: Float,
+    // Largest `Body::softening` in the node
+    pub softening: Float,
+    pub cube: Cube,
+    // Range into `Octree::points`
+    pub bodies: Range<usize>,
+}
+
+impl OctreeNode {
+    pub fn new(next: usize, cube: Cube) -> Self {
+        Self {
+            children: 0,
+            next,
+            pos: Vec3::zero(),
+            mass: 0.0,
+            softening: 0.0,
+            cube,
+            bodies: 0..0,
+        }
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.children == 0
+    }
+
+    pub fn is_branch(&self) -> bool {
+        self.children != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mass == 0.0
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Point3 {
+    pub pos: Vec3,
+    pub mass: Float,
+    pub softening: Float,
+    pub index: usize,
+}
+
+// Barnes-Hut tree for `Simulation3`, built the same way as `Quadtree::build` with monopole
+// moments and the geometric opening criterion
+#[derive(Clone)]
+pub struct Octree {
+    pub t_sq: Float,
+    pub epsilon: Float,
+    pub softening: Softening,
+    pub leaf_capacity: usize,
+    pub nodes: Vec<OctreeNode>,
+    pub parents: Vec<usize>,
+    pub points: Vec<Point3>,
+}
+
+impl Octree {
+    pub const ROOT: usize = 0;
+
+    pub fn new(theta: Float, epsilon: Float) -> Self {
+        Self {
+            t_sq: theta * theta,
+            epsilon,
+            softening: Softening::Plummer,
+            leaf_capacity: 1,
+            nodes: Vec::new(),
+            parents: Vec::new(),
+            points: Vec::new(),
+        }
+    }
+
+    pub fn clear(&mut self, cube: Cube) {
+        self.nodes.clear();
+        self.parents.clear();
+        self.points.clear();
+        self.nodes.push(OctreeNode::new(0, cube));
+    }
+
+    fn subdivide(&mut self, node: usize) -> usize {
+        self.parents.push(node);
+        let children = self.nodes.len();
+        self.nodes[node].children = children;
+
+        let next = self.nodes[node].next;
+        let cubes = self.nodes[node].cube.subdivide();
+        for (i, cube) in cubes.into_iter().enumerate() {
+            let next = if i < 7 { children + i + 1 } else { next };
+            self.nodes.push(OctreeNode::new(next, cube));
+        }
+
+        children
+    }
+
+    // Builds the whole tree at once, letting each leaf hold up to `leaf_capacity` bodies
+    pub fn build(&mut self, bodies: &[Body3]) {
+        self.clear(Cube::new_containing(bodies));
+
+        self.points
+            .extend(bodies.iter().enumerate().map(|(index, body)| Point3 {
+                pos: body.pos,
+                mass: body.mass,
+                softening: body.softening,
+                index,
+            }));
+        self.nodes[Self::ROOT].bodies = 0..self.points.len();
+
+        // Nodes are visited in the order they are created, so every node is split before its children
+        let mut node = Self::ROOT;
+        while node < self.nodes.len() {
+            let range = self.nodes[node].bodies.clone();
+
+            if range.len() <= self.leaf_capacity || self.is_coincident(range.clone()) {
+                self.summarize_leaf(node);
+            } else {
+                let center = self.nodes[node].cube.center;
+                let points = &mut self.points[range.clone()];
+
+                // Split along z, then y within each half, then x within each quarter
+                let mut splits = [range.start; 9];
+                splits[8] = range.end;
+                let z = partition(points, |p| p.pos.z <= center.z);
+                for (half, (start, end)) in [(0, z), (z, points.len())].into_iter().enumerate() {
+                    let y = start + partition(&mut points[start..end], |p| p.pos.y <= center.y);
+                    for (quarter, (start, end)) in [(start, y), (y, end)].into_iter().enumerate() {
+                        let x = start + partition(&mut points[start..end], |p| p.pos.x <= center.x);
+                        let octant = half * 4 + quarter * 2;
+                        splits[octant] = range.start + start;
+                        splits[octant + 1] = range.start + x;
+                    }
+                }
+
+                let children = self.subdivide(node);
+                for i in 0..8 {
+                    self.nodes[children + i].bodies = splits[i]..splits[i + 1];
+                }
+            }
+
+            node += 1;
+        }
+
+        self.propagate();
+    }
+
+    fn is_coincident(&self, range: Range<usize>) -> bool {
+        let points = &self.points[range];
+        points.iter().all(|p| p.pos == points[0].pos)
+    }
+
+    fn summarize_leaf(&mut self, node: usize) {
+        let n = &mut self.nodes[node];
+        let points = &self.points[n.bodies.clone()];
+
+        let mut pos = Vec3::zero();
+        let mut mass = 0.0;
+        let mut softening: Float = 0.0;
+        for p in points {
+            pos += p.pos * p.mass;
+            mass += p.mass;
+            softening = softening.max(p.softening);
+        }
+        if mass != 0.0 {
+            pos /= mass;
+        }
+
+        // Same as `Quadtree`, a lone body must not attract itself through rounding
+        if let [p] = points {
+            pos = p.pos;
+        }
+
+        n.pos = pos;
+        n.mass = mass;
+        n.softening = softening;
+    }
+
+    pub fn propagate(&mut self) {
+        for &node in self.parents.iter().rev() {
+            let i = self.nodes[node].children;
+            let children = &self.nodes[i..i + 8];
+
+            let mut pos = Vec3::zero();
+            let mut mass = 0.0;
+            let mut softening: Float = 0.0;
+            for child in children {
+                pos += child.pos * child.mass;
+                mass += child.mass;
+                softening = softening.max(child.softening);
+            }
+            pos /= mass;
+
+            let n = &mut self.nodes[node];
+            n.pos = pos;
+            n.mass = mass;
+            n.softening = softening;
+        }
+    }
+
+    // Acceleration at an arbitrary point, as if a massless body with no softening of its own sat there
+    pub fn acc(&self, pos: Vec3) -> Vec3 {
+        self.acc_with(pos, 0.0)
+    }
+
+    pub fn acc_of(&self, body: &Body3) -> Vec3 {
+        self.acc_with(body.pos, body.softening)
+    }
+
+    fn acc_with(&self, pos: Vec3, softening: Float) -> Vec3 {
+        let epsilon = self.epsilon.max(softening);
+        let mut acc = Vec3::zero();
+
+        let mut node = Self::ROOT;
+        loop {
+            let n = &self.nodes[node];
+
+            let d = n.pos - pos;
+            let d_sq = d.mag_sq();
+
+            let far = n.cube.size * n.cube.size < d_sq * self.t_sq;
+            if n.is_leaf() || far {
+                if !far && n.bodies.len() > 1 {
+                    for p in &self.points[n.bodies.clone()] {
+                        let d = p.pos - pos;
+                        let e = epsilon.max(p.softening);
+                        acc += d * self.softening.factor(d.mag_sq(), p.mass, e);
+                    }
+                } else {
+                    let e = epsilon.max(n.softening);
+                    acc += d * self.softening.factor(d_sq, n.mass, e);
+                }
+
+                if n.next == 0 {
+                    break;
+                }
+                node = n.next;
+            } else {
+                node = n.children;
+            }
+        }
+
+        acc
+    }
+
+    pub fn potential(&self, pos: Vec3) -> Float {
+        self.potential_with(pos, 0.0)
+    }
+
+    pub fn potential_of(&self, body: &Body3) -> Float {
+        self.potential_with(body.pos, body.softening)
+    }
+
+    // Potential at `pos`, found with the same traversal as `acc`, skipping bodies exactly at `pos`
+    fn potential_with(&self, pos: Vec3, softening: Float) -> Float {
+        let epsilon = self.epsilon.max(softening);
+        let mut potential = 0.0;
+
+        let mut node = Self::ROOT;
+        loop {
+            let n = &self.nodes[node];
+
+            let d_sq = (n.pos - pos).mag_sq();
+
+            let far = n.cube.size * n.cube.size < d_sq * self.t_sq;
+            if n.is_leaf() || far {
+                if !far && n.bodies.len() > 1 {
+                    for p in &self.points[n.bodies.clone()] {
+                        let e = epsilon.max(p.softening);
+                        potential += self.softening.potential((p.pos - pos).mag_sq(), p.mass, e);
+                    }
+                } else {
+                    let e = epsilon.max(n.softening);
+                    potential += self.softening.potential(d_sq, n.mass, e);
+                }
+
+                if n.next == 0 {
+                    break;
+                }
+                node = n.next;
+            } else {
+                node = n.children;
+            }
+        }
+
+        potential
+    }
+}
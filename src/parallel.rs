@@ -0,0 +1,48 @@
+use std::{num::NonZeroUsize, sync::Mutex, thread};
+
+// Resolves a thread count setting, where 0 means one thread per available core
+pub fn thread_count(threads: usize) -> usize {
+    if threads == 0 {
+        thread::available_parallelism().map_or(1, NonZeroUsize::get)
+    } else {
+        threads
+    }
+}
+
+// Calls `f` on every chunk of `items`, with idle threads picking up the next unclaimed chunk.
+// Each chunk is processed by exactly one thread, so the result doesn't depend on the thread count.
+pub fn for_each_chunk<T, F>(items: &mut [T], threads: usize, chunk_size: usize, f: F)
+where
+    T: Send,
+    F: Fn(&mut [T]) + Sync,
+{
+    for_each_chunk_indexed(items, threads, chunk_size, |_, chunk| f(chunk));
+}
+
+// Like `for_each_chunk`, but also passes the index in `items` of each chunk's first item
+pub fn for_each_chunk_indexed<T, F>(items: &mut [T], threads: usize, chunk_size: usize, f: F)
+where
+    T: Send,
+    F: Fn(usize, &mut [T]) + Sync,
+{
+    let chunk_size = chunk_size.max(1);
+    let threads = thread_count(threads);
+    if threads <= 1 || items.len() <= chunk_size {
+        for (i, chunk) in items.chunks_mut(chunk_size).enumerate() {
+            f(i * chunk_size, chunk);
+        }
+        return;
+    }
+
+    let chunks = Mutex::new(items.chunks_mut(chunk_size).enumerate());
+    thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| loop {
+                let Some((i, chunk)) = chunks.lock().unwrap().next() else {
+                    break;
+                };
+                f(i * chunk_size, chunk);
+            });
+        }
+    });
+}
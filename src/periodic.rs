@@ -0,0 +1,267 @@
+use std::sync::Arc;
+
+use crate::float::{widen, Float, Vec2};
+
+// Cells of the Ewald table along each axis of the quarter box `[0, size / 2]²`
+const EWALD_CELLS: usize = 64;
+
+// Lattice vectors and wave vectors summed over in each direction, which is plenty with
+// `EWALD_ALPHA` since both sums fall off like `erfc(2n)`
+const EWALD_TERMS: i32 = 4;
+
+// Splitting parameter in units of `1 / size`
+const EWALD_ALPHA: f64 = 2.0;
+
+// Square box of side `size` centered on the origin, repeated infinitely in both directions
+#[derive(Clone)]
+pub struct Periodic {
+    size: Float,
+    ewald: Option<Arc<Ewald>>,
+}
+
+impl Periodic {
+    // With `ewald` unset only the nearest image of every body is felt
+    pub fn new(size: Float, ewald: bool) -> Self {
+        Self {
+            size,
+            ewald: ewald.then(|| Arc::new(Ewald::new(size))),
+        }
+    }
+
+    pub fn size(&self) -> Float {
+        self.size
+    }
+
+    pub fn wrap(&self, pos: Vec2) -> Vec2 {
+        let wrap = |x: Float| x - (x / self.size + 0.5).floor() * self.size;
+        Vec2::new(wrap(pos.x), wrap(pos.y))
+    }
+
+    // Shortest displacement between any two images
+    pub fn nearest(&self, d: Vec2) -> Vec2 {
+        let nearest = |x: Float| x - (x / self.size).round() * self.size;
+        Vec2::new(nearest(d.x), nearest(d.y))
+    }
+
+    // Acceleration from all other images of `mass` at the nearest-image displacement `d`, which
+    // gets added to the softened acceleration from the nearest image itself
+    pub fn correction_acc(&self, d: Vec2, mass: Float) -> Vec2 {
+        match &self.ewald {
+            Some(ewald) => ewald.acc(d) * mass,
+            None => Vec2::zero(),
+        }
+    }
+
+    pub fn correction_potential(&self, d: Vec2, mass: Float) -> Float {
+        match &self.ewald {
+            Some(ewald) => ewald.potential(d) * mass,
+            None => 0.0,
+        }
+    }
+
+    // Whether a cell of side `size` centered at the nearest-image displacement `d` might have
+    // bodies whose nearest image is on the other side, in which case it has to be opened. With
+    // the Ewald correction the sum over all images is the same from any of them, so it never is.
+    pub fn straddles(&self, d: Vec2, size: Float) -> bool {
+        if self.ewald.is_some() {
+            return false;
+        }
+
+        let limit = 0.5 * (self.size - size);
+        d.x.abs() > limit || d.y.abs() > limit
+    }
+}
+
+// Tabulated difference between the Ewald sum over all images of a unit mass and its nearest
+// image. Bodies lie in the plane of a 2D lattice but still attract with the 3D force law, so this
+// is the quasi-2D Ewald sum, with a uniform background that cancels the mean density.
+struct Ewald {
+    size: Float,
+    // `[acc.x, acc.y, potential]` for displacements in the quarter box, row by row in `y`
+    table: Vec<[Float; 3]>,
+}
+
+impl Ewald {
+    fn new(size: Float) -> Self {
+        let size_f64 = widen(size);
+        let step = 0.5 * size_f64 / EWALD_CELLS as f64;
+
+        let mut table = Vec::with_capacity((EWALD_CELLS + 1) * (EWALD_CELLS + 1));
+        for j in 0..=EWALD_CELLS {
+            for i in 0..=EWALD_CELLS {
+                let (x, y) = (i as f64 * step, j as f64 * step);
+                let [ax, ay, potential] = ewald_sum(x, y, size_f64);
+                table.push([ax as Float, ay as Float, potential as Float]);
+            }
+        }
+
+        Self { size, table }
+    }
+
+    // Bilinear interpolation in the quarter box, using that the correction is odd along each
+    // axis for the acceleration and even for the potential
+    fn lookup(&self, d: Vec2) -> ([Float; 3], Vec2) {
+        let scale = EWALD_CELLS as Float * 2.0 / self.size;
+        let u = (d.x.abs() * scale).min(EWALD_CELLS as Float);
+        let v = (d.y.abs() * scale).min(EWALD_CELLS as Float);
+
+        let i = (u as usize).min(EWALD_CELLS - 1);
+        let j = (v as usize).min(EWALD_CELLS - 1);
+        let (fu, fv) = (u - i as Float, v - j as Float);
+
+        let row = EWALD_CELLS + 1;
+        let corners = [
+            (self.table[j * row + i], (1.0 - fu) * (1.0 - fv)),
+            (self.table[j * row + i + 1], fu * (1.0 - fv)),
+            (self.table[(j + 1) * row + i], (1.0 - fu) * fv),
+            (self.table[(j + 1) * row + i + 1], fu * fv),
+        ];
+
+        let mut value = [0.0; 3];
+        for (corner, weight) in corners {
+            for (v, c) in value.iter_mut().zip(corner) {
+                *v += c * weight;
+            }
+        }
+
+        (value, Vec2::new(d.x.signum(), d.y.signum()))
+    }
+
+    fn acc(&self, d: Vec2) -> Vec2 {
+        let ([ax, ay, _], sign) = self.lookup(d);
+        Vec2::new(ax, ay) * sign
+    }
+
+    fn potential(&self, d: Vec2) -> Float {
+        let ([_, _, potential], _) = self.lookup(d);
+        potential
+    }
+}
+
+// Ewald correction felt by a point at displacement `(x, y)` from it to a unit mass, as
+// `[acc.x, acc.y, potential]`, with the nearest image already subtracted
+fn ewald_sum(x: f64, y: f64, size: f64) -> [f64; 3] {
+    use std::f64::consts::{PI, TAU};
+
+    let alpha = EWALD_ALPHA / size;
+    let area = size * size;
+
+    let mut acc = [0.0, 0.0];
+    let mut potential = -2.0 * PI.sqrt() / (area * alpha);
+
+    // Short-range part, summed over lattice vectors
+    for nx in -EWALD_TERMS..=EWALD_TERMS {
+        for ny in -EWALD_TERMS..=EWALD_TERMS {
+            let (dx, dy) = (x + nx as f64 * size, y + ny as f64 * size);
+            let r = (dx * dx + dy * dy).sqrt();
+            let gauss = 2.0 * alpha / PI.sqrt() * (-alpha * alpha * r * r).exp();
+
+            // The nearest image cancels out of the correction, which leaves `-erf` in place of
+            // `erfc` and stays finite at `r = 0`
+            let (short, f) = if nx == 0 && ny == 0 {
+                if r == 0.0 {
+                    potential -= 2.0 * alpha / PI.sqrt();
+                    continue;
+                }
+                (-erf(alpha * r), -erf(alpha * r) + r * gauss)
+            } else {
+                (erfc(alpha * r), erfc(alpha * r) + r * gauss)
+            };
+
+            potential += short / r;
+            let r_cubed = r * r * r;
+            acc[0] += dx * f / r_cubed;
+            acc[1] += dy * f / r_cubed;
+        }
+    }
+
+    // Long-range part, summed over wave vectors
+    for hx in -EWALD_TERMS..=EWALD_TERMS {
+        for hy in -EWALD_TERMS..=EWALD_TERMS {
+            if hx == 0 && hy == 0 {
+                continue;
+            }
+
+            let (kx, ky) = (TAU * hx as f64 / size, TAU * hy as f64 / size);
+            let k = (kx * kx + ky * ky).sqrt();
+            let damping = TAU / area * erfc(k / (2.0 * alpha)) / k;
+            let phase = kx * x + ky * y;
+
+            potential += damping * phase.cos();
+            acc[0] += damping * kx * phase.sin();
+            acc[1] += damping * ky * phase.sin();
+        }
+    }
+
+    // Gravity attracts, so the potential is the negative of the Coulomb-like sum
+    [acc[0], acc[1], -potential]
+}
+
+pub(crate) fn erf(x: f64) -> f64 {
+    1.0 - erfc(x)
+}
+
+// Complementary error function, from the Chebyshev fit in Numerical Recipes with a relative error
+// below 1.2e-7, which is well within the accuracy of the tree
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let poly = -z * z - 1.26551223
+        + t * (1.00002368
+            + t * (0.37409196
+                + t * (0.09678418
+                    + t * (-0.18628806
+                        + t * (0.27886807
+                            + t * (-1.13520398
+                                + t * (1.48851587 + t * (-0.82215223 + t * 0.17087277))))))));
+    let value = t * poly.exp();
+    if x >= 0.0 {
+        value
+    } else {
+        2.0 - value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Acceleration from every image of a unit mass but the nearest, at displacement `d` from the
+    // point to the mass. Images come in symmetric shells, whose sum converges like `1 / shells`.
+    fn image_sum(d: Vec2, size: Float, shells: i32) -> (f64, f64) {
+        let (x, y, size) = (widen(d.x), widen(d.y), widen(size));
+        let mut acc = (0.0, 0.0);
+        for nx in -shells..=shells {
+            for ny in -shells..=shells {
+                if nx == 0 && ny == 0 {
+                    continue;
+                }
+                let (dx, dy) = (x + nx as f64 * size, y + ny as f64 * size);
+                let r_cubed = (dx * dx + dy * dy).powf(1.5);
+                acc.0 += dx / r_cubed;
+                acc.1 += dy / r_cubed;
+            }
+        }
+        acc
+    }
+
+    #[test]
+    fn correction_matches_the_sum_over_images() {
+        let size = 1000.0;
+        let periodic = Periodic::new(size, true);
+
+        for (x, y) in [(0.3, 0.0), (-0.1, 0.4), (0.25, -0.2), (0.45, 0.45)] {
+            let d = Vec2::new(x * size, y * size);
+            let acc = periodic.correction_acc(d, 1.0);
+            let (ax, ay) = image_sum(d, size, 300);
+
+            let scale = (ax * ax + ay * ay).sqrt();
+            let error = (widen(acc.x) - ax).hypot(widen(acc.y) - ay);
+            assert!(error < 0.01 * scale, "{d:?}: {acc:?} vs ({ax}, {ay})");
+        }
+
+        // A point just left of a mass is pulled further left by the far images on its side
+        let acc = periodic.correction_acc(Vec2::new(0.3 * size, 0.0), 1.0);
+        assert!(acc.x < 0.0);
+    }
+}
@@ -0,0 +1,59 @@
+use crate::float::{Float, Mat3, Vec2, Vec3};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ProjectionKind {
+    #[default]
+    Orthographic,
+    Perspective,
+}
+
+// Maps 3D positions onto the view plane for drawing. With no rotation the camera looks down the
+// z axis, so the xy plane is seen the same way as in 2D.
+#[derive(Clone, Copy, Debug)]
+pub struct Projection {
+    pub kind: ProjectionKind,
+    // Rotation about the z axis, then about the view's horizontal axis, in radians
+    pub yaw: Float,
+    pub pitch: Float,
+    // Distance from the camera to the origin, only used by `ProjectionKind::Perspective`
+    pub distance: Float,
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Self {
+            kind: ProjectionKind::Orthographic,
+            yaw: 0.0,
+            pitch: 0.0,
+            distance: 1000.0,
+        }
+    }
+}
+
+impl Projection {
+    pub fn rotation(&self) -> Mat3 {
+        Mat3::from_rotation_x(self.pitch) * Mat3::from_rotation_z(self.yaw)
+    }
+
+    // Position on the view plane and how much lengths at `pos` are scaled, or `None` if `pos` is
+    // behind the camera
+    pub fn project(&self, pos: Vec3) -> Option<(Vec2, Float)> {
+        self.project_rotated(self.rotation() * pos)
+    }
+
+    // Same as `project` with `rotation()` already applied, to avoid recomputing it for every body
+    pub fn project_rotated(&self, pos: Vec3) -> Option<(Vec2, Float)> {
+        match self.kind {
+            ProjectionKind::Orthographic => Some((pos.xy(), 1.0)),
+            ProjectionKind::Perspective => {
+                let depth = self.distance - pos.z;
+                if depth <= 0.0 {
+                    return None;
+                }
+
+                let scale = self.distance / depth;
+                Some((pos.xy() * scale, scale))
+            }
+        }
+    }
+}
@@ -1,18 +1,26 @@
-use crate::body::Body;
-use ultraviolet::Vec2;
+use std::{ops::Range, str::FromStr};
+
+use crate::{
+    body::Body,
+    float::{widen, Float, Vec2},
+    kernel::Softening,
+    parallel,
+    periodic::Periodic,
+};
+use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Copy)]
 pub struct Quad {
     pub center: Vec2,
-    pub size: f32,
+    pub size: Float,
 }
 
 impl Quad {
     pub fn new_containing(bodies: &[Body]) -> Self {
-        let mut min_x = f32::MAX;
-        let mut min_y = f32::MAX;
-        let mut max_x = f32::MIN;
-        let mut max_y = f32::MIN;
+        let mut min_x = Float::MAX;
+        let mut min_y = Float::MAX;
+        let mut max_x = Float::MIN;
+        let mut max_y = Float::MIN;
 
         for body in bodies {
             min_x = min_x.min(body.pos.x);
@@ -33,8 +41,8 @@ impl Quad {
 
     pub fn into_quadrant(mut self, quadrant: usize) -> Self {
         self.size *= 0.5;
-        self.center.x += ((quadrant & 1) as f32 - 0.5) * self.size;
-        self.center.y += ((quadrant >> 1) as f32 - 0.5) * self.size;
+        self.center.x += ((quadrant & 1) as Float - 0.5) * self.size;
+        self.center.y += ((quadrant >> 1) as Float - 0.5) * self.size;
         self
     }
 
@@ -43,13 +51,88 @@ impl Quad {
     }
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Multipole {
+    #[default]
+    Monopole,
+    Quadrupole,
+}
+
+impl FromStr for Multipole {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "monopole" => Ok(Self::Monopole),
+            "quadrupole" => Ok(Self::Quadrupole),
+            _ => Err(format!("unknown multipole '{s}'")),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Construction {
+    // Cells split around their centers, the same cells that `Quadtree::insert` gives one body at
+    // a time, see `Quadtree::build`
+    #[default]
+    Insert,
+    // Sorted by Morton key first, see `Quadtree::build_morton`
+    Morton,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Opening {
+    // `size < theta * d`, with `d` the distance to the center of mass
+    #[default]
+    Geometric,
+    // Salmon-Warren: `bmax < theta * d`, with `bmax` the distance from the center of mass to
+    // the farthest corner of the cell. This accepts more nodes than `Geometric` at the same
+    // `theta`: on the default disc the worst relative errors at `theta = 1` are around 2 without
+    // the box guard and still above 1 with it, and `theta = 0.7` does about as well as
+    // `Geometric` at 1.
+    Bmax,
+    // Gadget-style: `m size² < alpha |a_old| d⁴`, relative to the previous acceleration.
+    // Falls back to `Geometric` when there is no previous acceleration. Like Gadget, it needs
+    // the box guard to not accept cells around the body.
+    Relative,
+}
+
+impl Opening {
+    // Whether the box guard is on unless configured otherwise
+    pub fn guarded(self) -> bool {
+        self != Self::Geometric
+    }
+}
+
+impl FromStr for Opening {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "geometric" => Ok(Self::Geometric),
+            "bmax" => Ok(Self::Bmax),
+            "relative" => Ok(Self::Relative),
+            _ => Err(format!("unknown opening criterion '{s}'")),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Node {
     pub children: usize,
     pub next: usize,
     pub pos: Vec2,
-    pub mass: f32,
+    pub mass: Float,
+    // Traceless quadrupole moment (xx, xy, yy) about `pos`
+    pub quadrupole: [Float; 3],
+    // Largest `Body::softening` in the node
+    pub softening: Float,
     pub quad: Quad,
+    // Range into `Quadtree::points`, empty when built with `Quadtree::insert`
+    pub bodies: Range<usize>,
 }
 
 impl Node {
@@ -59,7 +142,10 @@ impl Node {
             next,
             pos: Vec2::zero(),
             mass: 0.0,
+            quadrupole: [0.0; 3],
+            softening: 0.0,
             quad,
+            bodies: 0..0,
         }
     }
 
@@ -74,53 +160,86 @@ impl Node {
     pub fn is_empty(&self) -> bool {
         self.mass == 0.0
     }
+
+    // Distance from the center of mass to the farthest corner of the cell
+    pub fn bmax(&self) -> Float {
+        let half = self.quad.size * 0.5;
+        let dx = half + (self.pos.x - self.quad.center.x).abs();
+        let dy = half + (self.pos.y - self.quad.center.y).abs();
+        (dx * dx + dy * dy).sqrt()
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Point {
+    pub pos: Vec2,
+    pub mass: Float,
+    pub softening: Float,
+    pub index: usize,
 }
 
 pub struct Quadtree {
-    pub t_sq: f32,
-    pub e_sq: f32,
+    pub t_sq: Float,
+    pub epsilon: Float,
+    pub softening: Softening,
+    pub opening: Opening,
+    // Tolerance of `Opening::Relative`
+    pub alpha: Float,
+    // Always open nodes whose cell, grown by 20%, contains the position being evaluated
+    pub box_guard: bool,
+    pub multipole: Multipole,
+    pub leaf_capacity: usize,
+    // Makes the root the periodic box and bodies attract through their nearest images
+    pub periodic: Option<Periodic>,
     pub nodes: Vec<Node>,
     pub parents: Vec<usize>,
+    pub points: Vec<Point>,
 }
 
 impl Quadtree {
     pub const ROOT: usize = 0;
 
-    pub fn new(theta: f32, epsilon: f32) -> Self {
+    pub fn new(theta: Float, epsilon: Float) -> Self {
         Self {
             t_sq: theta * theta,
-            e_sq: epsilon * epsilon,
+            epsilon,
+            softening: Softening::Plummer,
+            opening: Opening::Geometric,
+            alpha: 0.005,
+            box_guard: false,
+            multipole: Multipole::Monopole,
+            leaf_capacity: 1,
+            periodic: None,
             nodes: Vec::new(),
             parents: Vec::new(),
+            points: Vec::new(),
+        }
+    }
+
+    // Quad of the root node, the periodic box if there is one
+    pub fn bounds(&self, bodies: &[Body]) -> Quad {
+        match &self.periodic {
+            Some(periodic) => Quad {
+                center: Vec2::zero(),
+                size: periodic.size(),
+            },
+            None => Quad::new_containing(bodies),
         }
     }
 
     pub fn clear(&mut self, quad: Quad) {
         self.nodes.clear();
         self.parents.clear();
+        self.points.clear();
         self.nodes.push(Node::new(0, quad));
     }
 
     fn subdivide(&mut self, node: usize) -> usize {
-        self.parents.push(node);
-        let children = self.nodes.len();
-        self.nodes[node].children = children;
-
-        let nexts = [
-            children + 1,
-            children + 2,
-            children + 3,
-            self.nodes[node].next,
-        ];
-        let quads = self.nodes[node].quad.subdivide();
-        for i in 0..4 {
-            self.nodes.push(Node::new(nexts[i], quads[i]));
-        }
-
-        return children;
+        subdivide(&mut self.nodes, &mut self.parents, node)
     }
 
-    pub fn insert(&mut self, pos: Vec2, mass: f32) {
+    pub fn insert(&mut self, body: &Body) {
+        let (pos, mass, softening) = (body.pos, body.mass, body.softening);
         let mut node = Self::ROOT;
 
         while self.nodes[node].is_branch() {
@@ -131,12 +250,15 @@ impl Quadtree {
         if self.nodes[node].is_empty() {
             self.nodes[node].pos = pos;
             self.nodes[node].mass = mass;
+            self.nodes[node].softening = softening;
             return;
         }
 
-        let (p, m) = (self.nodes[node].pos, self.nodes[node].mass);
+        let n = &self.nodes[node];
+        let (p, m, e) = (n.pos, n.mass, n.softening);
         if pos == p {
             self.nodes[node].mass += mass;
+            self.nodes[node].softening = e.max(softening);
             return;
         }
 
@@ -154,45 +276,311 @@ impl Quadtree {
 
                 self.nodes[n1].pos = p;
                 self.nodes[n1].mass = m;
+                self.nodes[n1].softening = e;
                 self.nodes[n2].pos = pos;
                 self.nodes[n2].mass = mass;
+                self.nodes[n2].softening = softening;
                 return;
             }
         }
     }
 
+    // Builds the whole tree at once, letting each leaf hold up to `leaf_capacity` bodies. Cells
+    // are split around their centers like `insert` does, so with one body per leaf the tree has
+    // the same cells.
+    pub fn build(&mut self, bodies: &[Body]) {
+        self.build_parallel(bodies, 1);
+    }
+
+    // Same tree as `build`, node for node, with the subtrees built on separate threads
+    pub fn build_parallel(&mut self, bodies: &[Body], threads: usize) {
+        self.clear(self.bounds(bodies));
+
+        self.points
+            .extend(bodies.iter().enumerate().map(|(index, body)| Point {
+                pos: body.pos,
+                mass: body.mass,
+                softening: body.softening,
+                index,
+            }));
+        self.nodes[Self::ROOT].bodies = 0..self.points.len();
+
+        self.build_subtrees(Split::Partition, threads);
+    }
+
+    // Builds the tree from bodies sorted along a Z-order curve, so that the nodes of every subtree
+    // and the points of every leaf end up next to each other in memory
+    pub fn build_morton(&mut self, bodies: &[Body]) {
+        self.build_morton_parallel(bodies, 1);
+    }
+
+    // Same tree as `build_morton`, node for node, with the subtrees built on separate threads
+    pub fn build_morton_parallel(&mut self, bodies: &[Body], threads: usize) {
+        let quad = self.bounds(bodies);
+        self.clear(quad);
+
+        let min = quad.center - Vec2::one() * (quad.size * 0.5);
+        let scale = if quad.size > 0.0 {
+            (u32::MAX as f64) / widen(quad.size)
+        } else {
+            0.0
+        };
+
+        let mut keys = (0..bodies.len())
+            .map(|index| (0, index))
+            .collect::<Vec<_>>();
+        parallel::for_each_chunk(&mut keys, threads, 4096, |keys| {
+            for (key, index) in keys {
+                *key = morton_key(bodies[*index].pos, min, scale);
+            }
+        });
+        keys.sort_unstable();
+
+        self.points.extend(keys.iter().map(|&(_, index)| Point {
+            pos: bodies[index].pos,
+            mass: bodies[index].mass,
+            softening: bodies[index].softening,
+            index,
+        }));
+        self.nodes[Self::ROOT].bodies = 0..self.points.len();
+
+        self.build_subtrees(Split::Morton(&keys), threads);
+    }
+
+    // Splits the root down to `SPLIT_DEPTH`, builds the subtrees below on separate threads and
+    // then appends them to `nodes` one after another. The split depth doesn't depend on the
+    // thread count, so neither does the tree.
+    fn build_subtrees(&mut self, split: Split, threads: usize) {
+        // Depth-first, so that a subtree's nodes are allocated before its siblings' nodes
+        let mut subtrees = Vec::new();
+        let mut stack = vec![(Self::ROOT, 0)];
+        while let Some((node, depth)) = stack.pop() {
+            if depth == SPLIT_DEPTH {
+                subtrees.push(Subtree::new(node, depth));
+                continue;
+            }
+
+            let (nodes, parents) = (&mut self.nodes, &mut self.parents);
+            match split_node(
+                nodes,
+                parents,
+                &mut self.points,
+                0,
+                split,
+                node,
+                depth,
+                self.leaf_capacity,
+            ) {
+                Some(children) => {
+                    for i in (0..4).rev() {
+                        stack.push((children + i, depth + 1));
+                    }
+                }
+                None => {
+                    let points = &self.points[self.nodes[node].bodies.clone()];
+                    summarize_leaf(&mut self.nodes[node], points, self.multipole);
+                }
+            }
+        }
+
+        // Subtrees cover increasing, disjoint ranges of points, so each gets its own slice
+        let mut work = Vec::with_capacity(subtrees.len());
+        let mut rest = &mut self.points[..];
+        let mut offset = 0;
+        for subtree in subtrees {
+            let range = self.nodes[subtree.node].bodies.clone();
+            let (_, tail) = rest.split_at_mut(range.start - offset);
+            let (points, tail) = tail.split_at_mut(range.len());
+            rest = tail;
+            offset = range.end;
+            work.push((subtree, points));
+        }
+
+        let (tree, leaf_capacity, multipole) = (&self.nodes, self.leaf_capacity, self.multipole);
+        parallel::for_each_chunk(&mut work, threads, 1, |work| {
+            for (subtree, points) in work {
+                subtree.build(&tree[subtree.node], points, split, leaf_capacity, multipole);
+            }
+        });
+
+        let subtrees = work
+            .into_iter()
+            .map(|(subtree, _)| subtree)
+            .collect::<Vec<_>>();
+        for subtree in subtrees {
+            self.append(subtree);
+        }
+
+        self.propagate_parallel(threads);
+    }
+
+    // Moves a subtree built on its own into `nodes` and `parents`, shifting its indices to match
+    fn append(&mut self, subtree: Subtree) {
+        // Local index 1 becomes `nodes.len()`, local index 0 is the subtree's root which already exists
+        let offset = self.nodes.len() - 1;
+        let next = self.nodes[subtree.node].next;
+
+        let root = &subtree.nodes[0];
+        let n = &mut self.nodes[subtree.node];
+        n.children = if root.is_branch() {
+            root.children + offset
+        } else {
+            0
+        };
+        n.pos = root.pos;
+        n.mass = root.mass;
+        n.quadrupole = root.quadrupole;
+        n.softening = root.softening;
+
+        for mut node in subtree.nodes.into_iter().skip(1) {
+            if node.is_branch() {
+                node.children += offset;
+            }
+            node.next = if node.next == 0 {
+                next
+            } else {
+                node.next + offset
+            };
+            self.nodes.push(node);
+        }
+
+        self.parents.extend(subtree.parents.iter().map(|&parent| {
+            if parent == 0 {
+                subtree.node
+            } else {
+                parent + offset
+            }
+        }));
+    }
+
     pub fn propagate(&mut self) {
         for &node in self.parents.iter().rev() {
-            let i = self.nodes[node].children;
+            let moments = self.combine(node);
+            moments.write(&mut self.nodes[node]);
+        }
+    }
+
+    // Same result as `propagate`, but one level at a time with each level spread across threads
+    pub fn propagate_parallel(&mut self, threads: usize) {
+        if parallel::thread_count(threads) <= 1 {
+            self.propagate();
+            return;
+        }
+
+        for level in self.levels().into_iter().rev() {
+            let mut level: Vec<_> = level
+                .into_iter()
+                .map(|node| (node, Moments::default()))
+                .collect();
+            let tree = &*self;
+            parallel::for_each_chunk(&mut level, threads, 256, |level| {
+                for (node, moments) in level {
+                    *moments = tree.combine(*node);
+                }
+            });
+
+            for (node, moments) in level {
+                moments.write(&mut self.nodes[node]);
+            }
+        }
+    }
+
+    // Branches grouped by depth, each level in the order of `parents`
+    pub(crate) fn levels(&self) -> Vec<Vec<usize>> {
+        // A node is always subdivided before its children, so its depth is known by then
+        let mut depths = vec![0; self.nodes.len()];
+        let mut levels: Vec<Vec<usize>> = Vec::new();
+        for &node in &self.parents {
+            let depth = depths[node];
+            let children = self.nodes[node].children;
+            depths[children..children + 4].fill(depth + 1);
+
+            if levels.len() <= depth {
+                levels.resize_with(depth + 1, Vec::new);
+            }
+            levels[depth].push(node);
+        }
+        levels
+    }
+
+    fn combine(&self, node: usize) -> Moments {
+        let i = self.nodes[node].children;
+        let children = &self.nodes[i..i + 4];
+
+        let mut pos = children[0].pos * children[0].mass
+            + children[1].pos * children[1].mass
+            + children[2].pos * children[2].mass
+            + children[3].pos * children[3].mass;
+
+        let mass = children[0].mass + children[1].mass + children[2].mass + children[3].mass;
 
-            self.nodes[node].pos = self.nodes[i].pos * self.nodes[i].mass
-                + self.nodes[i + 1].pos * self.nodes[i + 1].mass
-                + self.nodes[i + 2].pos * self.nodes[i + 2].mass
-                + self.nodes[i + 3].pos * self.nodes[i + 3].mass;
+        pos /= mass;
 
-            self.nodes[node].mass = self.nodes[i].mass
-                + self.nodes[i + 1].mass
-                + self.nodes[i + 2].mass
-                + self.nodes[i + 3].mass;
+        let quadrupole = if self.multipole == Multipole::Quadrupole {
+            combine_quadrupoles(children, pos)
+        } else {
+            [0.0; 3]
+        };
 
-            let mass = self.nodes[node].mass;
-            self.nodes[node].pos /= mass;
+        let softening = children
+            .iter()
+            .map(|child| child.softening)
+            .fold(0.0, Float::max);
+
+        Moments {
+            pos,
+            mass,
+            quadrupole,
+            softening,
         }
     }
 
+    // Acceleration at an arbitrary point, as if a massless body with no softening of its own sat there
     pub fn acc(&self, pos: Vec2) -> Vec2 {
+        self.acc_with(pos, 0.0, 0.0)
+    }
+
+    // Acceleration of `body`, using its own softening and, for `Opening::Relative`, its
+    // acceleration from the last step
+    pub fn acc_of(&self, body: &Body) -> Vec2 {
+        self.acc_with(body.pos, body.softening, body.acc.mag())
+    }
+
+    fn acc_with(&self, pos: Vec2, softening: Float, a_old: Float) -> Vec2 {
+        let epsilon = self.epsilon.max(softening);
         let mut acc = Vec2::zero();
 
         let mut node = Self::ROOT;
         loop {
             let n = &self.nodes[node];
 
-            let d = n.pos - pos;
+            let d = self.displacement(pos, n.pos);
             let d_sq = d.mag_sq();
 
-            if n.is_leaf() || n.quad.size * n.quad.size < d_sq * self.t_sq {
-                let denom = (d_sq + self.e_sq) * d_sq.sqrt();
-                acc += d * (n.mass / denom).min(f32::MAX);
+            let far = self.is_far(n, pos, d_sq, a_old);
+            if n.is_leaf() || far {
+                if !far && n.bodies.len() > 1 {
+                    for p in &self.points[n.bodies.clone()] {
+                        let d = self.displacement(pos, p.pos);
+                        let e = epsilon.max(p.softening);
+                        acc += self.softening.acc(d, d.mag_sq(), p.mass, e);
+                        if let Some(periodic) = &self.periodic {
+                            acc += periodic.correction_acc(d, p.mass);
+                        }
+                    }
+                } else {
+                    let e = epsilon.max(n.softening);
+                    acc += self.softening.acc(d, d_sq, n.mass, e);
+                    if let Some(periodic) = &self.periodic {
+                        acc += periodic.correction_acc(d, n.mass);
+                    }
+
+                    if self.multipole == Multipole::Quadrupole
+                        && (n.is_branch() || n.bodies.len() > 1)
+                    {
+                        acc += self.quadrupole_acc(n, d, e);
+                    }
+                }
 
                 if n.next == 0 {
                     break;
@@ -205,4 +593,496 @@ impl Quadtree {
 
         acc
     }
+
+    pub fn potential(&self, pos: Vec2) -> Float {
+        self.potential_with(pos, 0.0)
+    }
+
+    pub fn potential_of(&self, body: &Body) -> Float {
+        self.potential_with(body.pos, body.softening)
+    }
+
+    // Potential at `pos`, found with the same traversal as `acc`. Contributions from bodies
+    // exactly at `pos` are skipped, so that a body's own mass doesn't count towards it.
+    // `Opening::Relative` has no previous acceleration here and falls back to `Geometric`.
+    fn potential_with(&self, pos: Vec2, softening: Float) -> Float {
+        let epsilon = self.epsilon.max(softening);
+        let mut potential = 0.0;
+
+        let mut node = Self::ROOT;
+        loop {
+            let n = &self.nodes[node];
+
+            let d = self.displacement(pos, n.pos);
+            let d_sq = d.mag_sq();
+
+            let far = self.is_far(n, pos, d_sq, 0.0);
+            if n.is_leaf() || far {
+                if !far && n.bodies.len() > 1 {
+                    for p in &self.points[n.bodies.clone()] {
+                        let d = self.displacement(pos, p.pos);
+                        let e = epsilon.max(p.softening);
+                        potential += self.softening.potential(d.mag_sq(), p.mass, e);
+                        if let Some(periodic) = &self.periodic {
+                            potential += periodic.correction_potential(d, p.mass);
+                        }
+                    }
+                } else {
+                    let e = epsilon.max(n.softening);
+                    potential += self.softening.potential(d_sq, n.mass, e);
+                    if let Some(periodic) = &self.periodic {
+                        potential += periodic.correction_potential(d, n.mass);
+                    }
+
+                    if self.multipole == Multipole::Quadrupole
+                        && (n.is_branch() || n.bodies.len() > 1)
+                    {
+                        potential += self.quadrupole_potential(n, d, e);
+                    }
+                }
+
+                if n.next == 0 {
+                    break;
+                }
+                node = n.next;
+            } else {
+                node = n.children;
+            }
+        }
+
+        potential
+    }
+
+    fn is_far(&self, n: &Node, pos: Vec2, d_sq: Float, a_old: Float) -> bool {
+        let size_sq = n.quad.size * n.quad.size;
+        let far = match self.opening {
+            Opening::Geometric => size_sq < d_sq * self.t_sq,
+            Opening::Bmax => {
+                let bmax = n.bmax();
+                bmax * bmax < d_sq * self.t_sq
+            }
+            Opening::Relative if a_old == 0.0 => size_sq < d_sq * self.t_sq,
+            Opening::Relative => n.mass * size_sq < self.alpha * a_old * d_sq * d_sq,
+        };
+
+        if !far {
+            return false;
+        }
+
+        let r = self.displacement(pos, n.quad.center);
+        if let Some(periodic) = &self.periodic {
+            if periodic.straddles(r, n.quad.size) {
+                return false;
+            }
+        }
+
+        if self.box_guard {
+            let limit = 0.6 * n.quad.size;
+            return r.x.abs() > limit || r.y.abs() > limit;
+        }
+
+        true
+    }
+
+    // From `pos` to `target`, or to its nearest image with periodic boundaries
+    fn displacement(&self, pos: Vec2, target: Vec2) -> Vec2 {
+        match &self.periodic {
+            Some(periodic) => periodic.nearest(target - pos),
+            None => target - pos,
+        }
+    }
+
+    fn quadrupole_potential(&self, n: &Node, d: Vec2, epsilon: Float) -> Float {
+        let [xx, xy, yy] = n.quadrupole;
+        let d_q_d = xx * d.x * d.x + 2.0 * xy * d.x * d.y + yy * d.y * d.y;
+
+        let r_sq = d.mag_sq() + self.softening.quadrupole_e_sq(epsilon);
+        -0.5 * d_q_d / (r_sq * r_sq * r_sq.sqrt())
+    }
+
+    fn quadrupole_acc(&self, n: &Node, d: Vec2, epsilon: Float) -> Vec2 {
+        let [xx, xy, yy] = n.quadrupole;
+        let q_d = Vec2::new(xx * d.x + xy * d.y, xy * d.x + yy * d.y);
+        let d_q_d = d.dot(q_d);
+
+        let r_sq = d.mag_sq() + self.softening.quadrupole_e_sq(epsilon);
+        let inv_r_sq = 1.0 / r_sq;
+        let inv_r5 = inv_r_sq * inv_r_sq / r_sq.sqrt();
+
+        (d * (2.5 * d_q_d * inv_r_sq) - q_d) * inv_r5
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Moments {
+    pos: Vec2,
+    mass: Float,
+    quadrupole: [Float; 3],
+    softening: Float,
+}
+
+impl Moments {
+    fn write(self, node: &mut Node) {
+        node.pos = self.pos;
+        node.mass = self.mass;
+        node.quadrupole = self.quadrupole;
+        node.softening = self.softening;
+    }
+}
+
+// Part of a tree being built independently of the rest, with the subtree's root at index 0
+struct Subtree {
+    node: usize,
+    depth: u32,
+    nodes: Vec<Node>,
+    parents: Vec<usize>,
+}
+
+impl Subtree {
+    fn new(node: usize, depth: u32) -> Self {
+        Self {
+            node,
+            depth,
+            nodes: Vec::new(),
+            parents: Vec::new(),
+        }
+    }
+
+    // `points` are those of the subtree's root, whose ranges stay relative to all points
+    fn build(
+        &mut self,
+        root: &Node,
+        points: &mut [Point],
+        split: Split,
+        leaf_capacity: usize,
+        multipole: Multipole,
+    ) {
+        let offset = root.bodies.start;
+        let mut root = root.clone();
+        root.next = 0;
+        self.nodes.push(root);
+
+        let mut stack = vec![(0, self.depth)];
+        while let Some((node, depth)) = stack.pop() {
+            let (nodes, parents) = (&mut self.nodes, &mut self.parents);
+            match split_node(
+                nodes,
+                parents,
+                points,
+                offset,
+                split,
+                node,
+                depth,
+                leaf_capacity,
+            ) {
+                Some(children) => {
+                    for i in (0..4).rev() {
+                        stack.push((children + i, depth + 1));
+                    }
+                }
+                None => {
+                    let range = self.nodes[node].bodies.clone();
+                    let points = &points[range.start - offset..range.end - offset];
+                    summarize_leaf(&mut self.nodes[node], points, multipole);
+                }
+            }
+        }
+    }
+}
+
+// Depth down to which `build_subtrees` splits cells before building the subtrees below in
+// parallel, giving up to 256 of them
+const SPLIT_DEPTH: u32 = 4;
+
+// How cells are split between their children
+#[derive(Clone, Copy)]
+enum Split<'a> {
+    // By rearranging their points around the cell's center
+    Partition,
+    // By the next two bits of the Morton keys, which the points are already sorted by
+    Morton(&'a [(u64, usize)]),
+}
+
+fn subdivide(nodes: &mut Vec<Node>, parents: &mut Vec<usize>, node: usize) -> usize {
+    parents.push(node);
+    let children = nodes.len();
+    nodes[node].children = children;
+
+    let nexts = [children + 1, children + 2, children + 3, nodes[node].next];
+    let quads = nodes[node].quad.subdivide();
+    for i in 0..4 {
+        nodes.push(Node::new(nexts[i], quads[i]));
+    }
+
+    children
+}
+
+// Subdivides `node` if it has more than `leaf_capacity` points that aren't all in the same place,
+// splitting its range of points between the children. `points` starts at index `offset` of all
+// points.
+#[allow(clippy::too_many_arguments)]
+fn split_node(
+    nodes: &mut Vec<Node>,
+    parents: &mut Vec<usize>,
+    points: &mut [Point],
+    offset: usize,
+    split: Split,
+    node: usize,
+    depth: u32,
+    leaf_capacity: usize,
+) -> Option<usize> {
+    let range = nodes[node].bodies.clone();
+    if range.len() <= leaf_capacity {
+        return None;
+    }
+
+    let splits = match split {
+        Split::Partition => {
+            let points = &mut points[range.start - offset..range.end - offset];
+            if points.iter().all(|p| p.pos == points[0].pos) {
+                return None;
+            }
+
+            let center = nodes[node].quad.center;
+            let mid = partition(points, |p| p.pos.y <= center.y);
+            let low = partition(&mut points[..mid], |p| p.pos.x <= center.x);
+            let high = mid + partition(&mut points[mid..], |p| p.pos.x <= center.x);
+            [0, low, mid, high, range.len()].map(|i| range.start + i)
+        }
+        Split::Morton(keys) => {
+            let keys = &keys[range.clone()];
+            if keys[0].0 == keys[keys.len() - 1].0 || depth == MORTON_BITS {
+                return None;
+            }
+
+            let shift = 2 * (MORTON_BITS - 1 - depth);
+            let split = |q: u64| range.start + keys.partition_point(|&(k, _)| (k >> shift) & 3 < q);
+            [range.start, split(1), split(2), split(3), range.end]
+        }
+    };
+
+    let children = subdivide(nodes, parents, node);
+    for i in 0..4 {
+        nodes[children + i].bodies = splits[i]..splits[i + 1];
+    }
+
+    Some(children)
+}
+
+fn summarize_leaf(node: &mut Node, points: &[Point], multipole: Multipole) {
+    let mut pos = Vec2::zero();
+    let mut mass = 0.0;
+    for p in points {
+        pos += p.pos * p.mass;
+        mass += p.mass;
+    }
+    if mass != 0.0 {
+        pos /= mass;
+    }
+
+    // Exactly at the body, which rounding can miss, so that it doesn't attract itself when
+    // unsoftened
+    if let [p] = points {
+        pos = p.pos;
+    }
+
+    let mut quadrupole = [0.0; 3];
+    if multipole == Multipole::Quadrupole {
+        for p in points {
+            let s = p.pos - pos;
+            let s_sq = s.mag_sq();
+            quadrupole[0] += p.mass * (3.0 * s.x * s.x - s_sq);
+            quadrupole[1] += p.mass * (3.0 * s.x * s.y);
+            quadrupole[2] += p.mass * (3.0 * s.y * s.y - s_sq);
+        }
+    }
+
+    node.pos = pos;
+    node.mass = mass;
+    node.quadrupole = quadrupole;
+    node.softening = points.iter().map(|p| p.softening).fold(0.0, Float::max);
+}
+
+// Parallel-axis theorem: shift each child's moment from its center of mass to the parent's
+fn combine_quadrupoles(children: &[Node], center: Vec2) -> [Float; 3] {
+    let mut q = [0.0; 3];
+    for child in children {
+        if child.is_empty() {
+            continue;
+        }
+
+        let s = child.pos - center;
+        let s_sq = s.mag_sq();
+        let m = child.mass;
+
+        q[0] += child.quadrupole[0] + m * (3.0 * s.x * s.x - s_sq);
+        q[1] += child.quadrupole[1] + m * (3.0 * s.x * s.y);
+        q[2] += child.quadrupole[2] + m * (3.0 * s.y * s.y - s_sq);
+    }
+    q
+}
+
+const MORTON_BITS: u32 = 32;
+
+// Interleaves the quantized coordinates as ...y1x1y0x0, matching `Quad::find_quadrant`
+fn morton_key(pos: Vec2, min: Vec2, scale: f64) -> u64 {
+    let x = (widen(pos.x - min.x) * scale) as u32;
+    let y = (widen(pos.y - min.y) * scale) as u32;
+    spread_bits(y) << 1 | spread_bits(x)
+}
+
+fn spread_bits(v: u32) -> u64 {
+    let mut v = v as u64;
+    v = (v | v << 16) & 0x0000_ffff_0000_ffff;
+    v = (v | v << 8) & 0x00ff_00ff_00ff_00ff;
+    v = (v | v << 4) & 0x0f0f_0f0f_0f0f_0f0f;
+    v = (v | v << 2) & 0x3333_3333_3333_3333;
+    v = (v | v << 1) & 0x5555_5555_5555_5555;
+    v
+}
+
+// Moves the elements matching `pred` to the front and returns how many there are
+pub(crate) fn partition<T>(slice: &mut [T], pred: impl Fn(&T) -> bool) -> usize {
+    let mut split = 0;
+    for i in 0..slice.len() {
+        if pred(&slice[i]) {
+            slice.swap(split, i);
+            split += 1;
+        }
+    }
+    split
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{models, utils};
+
+    fn bodies() -> Vec<Body> {
+        let mut bodies = utils::uniform_disc(3000, 1);
+        // Coincident bodies end up in one leaf however many there are
+        let copies = bodies[10..14].to_vec();
+        bodies.extend(copies);
+        bodies
+    }
+
+    fn assert_same_tree(a: &Quadtree, b: &Quadtree) {
+        assert_eq!(a.nodes.len(), b.nodes.len());
+        for (a, b) in a.nodes.iter().zip(&b.nodes) {
+            assert_eq!(a.children, b.children);
+            assert_eq!(a.next, b.next);
+            assert_eq!(a.bodies, b.bodies);
+            assert_eq!(a.pos, b.pos);
+            assert_eq!(a.mass.to_bits(), b.mass.to_bits());
+            assert_eq!(a.quadrupole, b.quadrupole);
+            assert_eq!(a.softening, b.softening);
+            assert_eq!(a.quad.center, b.quad.center);
+        }
+        assert_eq!(a.parents, b.parents);
+
+        let indices = |tree: &Quadtree| tree.points.iter().map(|p| p.index).collect::<Vec<_>>();
+        assert_eq!(indices(a), indices(b));
+    }
+
+    #[test]
+    fn parallel_builds_match_serial_builds() {
+        let bodies = bodies();
+        for leaf_capacity in [1, 5] {
+            let tree = || {
+                let mut tree = Quadtree::new(0.7, 1.0);
+                tree.multipole = Multipole::Quadrupole;
+                tree.leaf_capacity = leaf_capacity;
+                tree
+            };
+
+            let mut serial = tree();
+            serial.build(&bodies);
+            let mut morton = tree();
+            morton.build_morton(&bodies);
+
+            for threads in [2, 3, 4, 8] {
+                let mut parallel = tree();
+                parallel.build_parallel(&bodies, threads);
+                assert_same_tree(&serial, &parallel);
+
+                parallel.build_morton_parallel(&bodies, threads);
+                assert_same_tree(&morton, &parallel);
+            }
+        }
+    }
+
+    #[test]
+    fn build_has_the_cells_of_insert() {
+        let bodies = bodies();
+        let mut built = Quadtree::new(0.7, 1.0);
+        built.build(&bodies);
+
+        let mut inserted = Quadtree::new(0.7, 1.0);
+        inserted.clear(inserted.bounds(&bodies));
+        for body in &bodies {
+            inserted.insert(body);
+        }
+        inserted.propagate();
+
+        let masses = |tree: &Quadtree| {
+            let mut cells = tree
+                .nodes
+                .iter()
+                .map(|n| {
+                    (
+                        n.quad.center.x.to_bits(),
+                        n.quad.center.y.to_bits(),
+                        n.mass.to_bits(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            cells.sort_unstable();
+            cells
+        };
+        assert_eq!(masses(&built), masses(&inserted));
+
+        for body in bodies.iter().step_by(97) {
+            assert_eq!(built.acc(body.pos), inserted.acc(body.pos));
+        }
+    }
+
+    // Median relative error of the accelerations at `theta = 0.7` against direct summation
+    fn median_error(bodies: &[Body], multipole: Multipole) -> f64 {
+        let mut tree = Quadtree::new(0.7, 1.0);
+        tree.multipole = multipole;
+        tree.build(bodies);
+
+        let mut errors = bodies
+            .iter()
+            .map(|body| {
+                let (mut x, mut y) = (0.0, 0.0);
+                for other in bodies {
+                    let d = other.pos - body.pos;
+                    let (dx, dy) = (widen(d.x), widen(d.y));
+                    let r_sq = dx * dx + dy * dy + 1.0;
+                    let factor = widen(other.mass) / (r_sq * r_sq.sqrt());
+                    (x, y) = (x + dx * factor, y + dy * factor);
+                }
+                let acc = tree.acc_of(body);
+                (widen(acc.x) - x).hypot(widen(acc.y) - y) / x.hypot(y)
+            })
+            .collect::<Vec<_>>();
+        errors.sort_by(f64::total_cmp);
+        errors[errors.len() / 2]
+    }
+
+    #[test]
+    fn quadrupoles_are_more_accurate_than_monopoles() {
+        for bodies in [
+            utils::uniform_disc(3000, 2),
+            models::plummer(3000, 100000.0, 300.0, 2),
+        ] {
+            let monopole = median_error(&bodies, Multipole::Monopole);
+            let quadrupole = median_error(&bodies, Multipole::Quadrupole);
+            assert!(
+                quadrupole < monopole / 3.0,
+                "{quadrupole:e} with quadrupoles and {monopole:e} without"
+            );
+        }
+    }
 }
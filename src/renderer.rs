@@ -1,11 +1,12 @@
-use std::{
-    f32::consts::{PI, TAU},
-    sync::atomic::{AtomicBool, Ordering},
-};
-
-use crate::{
-    body::Body,
-    quadtree::{Node, Quadtree},
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use barnes_hut::{
+    float::{
+        self,
+        consts::{PI, TAU},
+        from_f32, narrow, to_f32,
+    },
+    Body, Float, Node, Projection, ProjectionKind, Quadtree,
 };
 
 use quarkstrom::{egui, winit::event::VirtualKeyCode, winit_input_helper::WinitInputHelper};
@@ -17,6 +18,7 @@ use once_cell::sync::Lazy;
 use parking_lot::Mutex;
 
 pub static PAUSED: Lazy<AtomicBool> = Lazy::new(|| false.into());
+pub static SAVE: Lazy<AtomicBool> = Lazy::new(|| false.into());
 pub static UPDATE_LOCK: Lazy<Mutex<bool>> = Lazy::new(|| Mutex::new(false));
 
 pub static BODIES: Lazy<Mutex<Vec<Body>>> = Lazy::new(|| Mutex::new(Vec::new()));
@@ -24,6 +26,9 @@ pub static QUADTREE: Lazy<Mutex<Vec<Node>>> = Lazy::new(|| Mutex::new(Vec::new()
 
 pub static SPAWN: Lazy<Mutex<Vec<Body>>> = Lazy::new(|| Mutex::new(Vec::new()));
 
+pub static THREE_D: Lazy<AtomicBool> = Lazy::new(|| false.into());
+pub static PROJECTION: Lazy<Mutex<Projection>> = Lazy::new(|| Mutex::new(Projection::default()));
+
 pub struct Renderer {
     pos: Vec2,
     scale: f32,
@@ -36,8 +41,8 @@ pub struct Renderer {
     depth_range: (usize, usize),
 
     spawn_body: Option<Body>,
-    angle: Option<f32>,
-    total: Option<f32>,
+    angle: Option<Float>,
+    total: Option<Float>,
 
     confirmed_bodies: Option<Body>,
 
@@ -49,7 +54,12 @@ impl quarkstrom::Renderer for Renderer {
     fn new() -> Self {
         Self {
             pos: Vec2::zero(),
-            scale: 3600.0,
+            // The 3D initial conditions are a much smaller sphere than the 2D disc
+            scale: if THREE_D.load(Ordering::Relaxed) {
+                600.0
+            } else {
+                3600.0
+            },
 
             settings_window_open: false,
 
@@ -77,6 +87,36 @@ impl quarkstrom::Renderer for Renderer {
             PAUSED.store(!val, Ordering::Relaxed)
         }
 
+        if input.key_pressed(VirtualKeyCode::S) && !THREE_D.load(Ordering::Relaxed) {
+            SAVE.store(true, Ordering::Relaxed);
+        }
+
+        if THREE_D.load(Ordering::Relaxed) {
+            let mut projection = PROJECTION.lock();
+
+            // Radians per frame
+            let speed = 0.02;
+            if input.key_held(VirtualKeyCode::Left) {
+                projection.yaw -= speed;
+            }
+            if input.key_held(VirtualKeyCode::Right) {
+                projection.yaw += speed;
+            }
+            if input.key_held(VirtualKeyCode::Up) {
+                projection.pitch -= speed;
+            }
+            if input.key_held(VirtualKeyCode::Down) {
+                projection.pitch += speed;
+            }
+
+            if input.key_pressed(VirtualKeyCode::P) {
+                projection.kind = match projection.kind {
+                    ProjectionKind::Orthographic => ProjectionKind::Perspective,
+                    ProjectionKind::Perspective => ProjectionKind::Orthographic,
+                };
+            }
+        }
+
         if let Some((mx, my)) = input.mouse() {
             // Scroll steps to double/halve the scale
             let steps = 5.0;
@@ -102,19 +142,19 @@ impl quarkstrom::Renderer for Renderer {
             self.pos.y += mdy / height as f32 * self.scale * 2.0;
         }
 
-        let world_mouse = || -> Vec2 {
+        let world_mouse = || -> float::Vec2 {
             let (mx, my) = input.mouse().unwrap_or_default();
             let mut mouse = Vec2::new(mx, my);
             mouse *= 2.0 / height as f32;
             mouse.y -= 1.0;
             mouse.y *= -1.0;
             mouse.x -= width as f32 / height as f32;
-            mouse * self.scale + self.pos
+            from_f32(mouse * self.scale + self.pos)
         };
 
         if input.mouse_pressed(1) {
             let mouse = world_mouse();
-            self.spawn_body = Some(Body::new(mouse, Vec2::zero(), 1.0, 1.0));
+            self.spawn_body = Some(Body::new(mouse, float::Vec2::zero(), 1.0, 1.0));
             self.angle = None;
             self.total = Some(0.0);
         } else if input.mouse_held(1) {
@@ -165,18 +205,19 @@ impl quarkstrom::Renderer for Renderer {
         if !self.bodies.is_empty() {
             if self.show_bodies {
                 for i in 0..self.bodies.len() {
-                    ctx.draw_circle(self.bodies[i].pos, self.bodies[i].radius, [0xff; 4]);
+                    let body = &self.bodies[i];
+                    ctx.draw_circle(to_f32(body.pos), narrow(body.radius), [0xff; 4]);
                 }
             }
 
             if let Some(body) = &self.confirmed_bodies {
-                ctx.draw_circle(body.pos, body.radius, [0xff; 4]);
-                ctx.draw_line(body.pos, body.pos + body.vel, [0xff; 4]);
+                ctx.draw_circle(to_f32(body.pos), narrow(body.radius), [0xff; 4]);
+                ctx.draw_line(to_f32(body.pos), to_f32(body.pos + body.vel), [0xff; 4]);
             }
 
             if let Some(body) = &self.spawn_body {
-                ctx.draw_circle(body.pos, body.radius, [0xff; 4]);
-                ctx.draw_line(body.pos, body.pos + body.vel, [0xff; 4]);
+                ctx.draw_circle(to_f32(body.pos), narrow(body.radius), [0xff; 4]);
+                ctx.draw_line(to_f32(body.pos), to_f32(body.pos + body.vel), [0xff; 4]);
             }
         }
 
@@ -220,9 +261,9 @@ impl quarkstrom::Renderer for Renderer {
                     }
                 } else if depth >= min_depth {
                     let quad = node.quad;
-                    let half = Vec2::new(0.5, 0.5) * quad.size;
-                    let min = quad.center - half;
-                    let max = quad.center + half;
+                    let half = float::Vec2::new(0.5, 0.5) * quad.size;
+                    let min = to_f32(quad.center - half);
+                    let max = to_f32(quad.center + half);
 
                     let t = ((depth - min_depth + !node.is_empty() as usize) as f32)
                         / (max_depth - min_depth + 1) as f32;
@@ -258,6 +299,28 @@ impl quarkstrom::Renderer for Renderer {
                         ui.add(egui::DragValue::new(&mut range.1).speed(0.05));
                     });
                 }
+                if THREE_D.load(Ordering::Relaxed) {
+                    let mut projection = PROJECTION.lock();
+                    ui.horizontal(|ui| {
+                        ui.label("Projection:");
+                        ui.radio_value(
+                            &mut projection.kind,
+                            ProjectionKind::Orthographic,
+                            "Orthographic",
+                        );
+                        ui.radio_value(
+                            &mut projection.kind,
+                            ProjectionKind::Perspective,
+                            "Perspective",
+                        );
+                    });
+                    if projection.kind == ProjectionKind::Perspective {
+                        ui.horizontal(|ui| {
+                            ui.label("Camera Distance:");
+                            ui.add(egui::DragValue::new(&mut projection.distance).speed(1.0));
+                        });
+                    }
+                }
             });
     }
 }
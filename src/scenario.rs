@@ -0,0 +1,266 @@
+use crate::{
+    body::Body,
+    float::{consts, Float, Vec2},
+    utils,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Spin {
+    Clockwise,
+    Counterclockwise,
+}
+
+// One generated system, moved into place about its own center of mass
+pub struct Component {
+    pub bodies: Vec<Body>,
+    // Where the center of mass ends up
+    pub position: Vec2,
+    // Bulk velocity added to every body
+    pub velocity: Vec2,
+    // Counterclockwise, in radians
+    pub rotation: Float,
+    // Mirrors the system if it turns the other way, `None` keeps it as generated
+    pub spin: Option<Spin>,
+}
+
+impl Component {
+    pub fn new(bodies: Vec<Body>) -> Self {
+        Self {
+            bodies,
+            position: Vec2::zero(),
+            velocity: Vec2::zero(),
+            rotation: 0.0,
+            spin: None,
+        }
+    }
+
+    pub fn position(mut self, position: Vec2) -> Self {
+        self.position = position;
+        self
+    }
+
+    pub fn velocity(mut self, velocity: Vec2) -> Self {
+        self.velocity = velocity;
+        self
+    }
+
+    pub fn rotation(mut self, rotation: Float) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn spin(mut self, spin: Spin) -> Self {
+        self.spin = Some(spin);
+        self
+    }
+
+    pub fn mass(&self) -> Float {
+        self.bodies.iter().map(|body| body.mass).sum()
+    }
+
+    // Distance of the farthest body from the center of mass
+    pub fn radius(&self) -> Float {
+        let (center, _) = self.center();
+        self.bodies
+            .iter()
+            .map(|body| (body.pos - center).mag())
+            .fold(0.0, Float::max)
+    }
+
+    // Center-of-mass position and velocity
+    fn center(&self) -> (Vec2, Vec2) {
+        let mass = self.mass();
+        if mass == 0.0 {
+            return (Vec2::zero(), Vec2::zero());
+        }
+
+        let (pos, vel) = self
+            .bodies
+            .iter()
+            .fold((Vec2::zero(), Vec2::zero()), |(pos, vel), body| {
+                (pos + body.pos * body.mass, vel + body.vel * body.mass)
+            });
+        (pos / mass, vel / mass)
+    }
+
+    fn place(self) -> Vec<Body> {
+        let (center, drift) = self.center();
+
+        // Sign of the angular momentum about the center of mass
+        let turning = self.bodies.iter().fold(0.0, |sum, body| {
+            let (r, v) = (body.pos - center, body.vel - drift);
+            sum + body.mass * (r.x * v.y - r.y * v.x)
+        });
+        let mirror = match self.spin {
+            Some(Spin::Clockwise) => turning > 0.0,
+            Some(Spin::Counterclockwise) => turning < 0.0,
+            None => false,
+        };
+
+        let (sin, cos) = self.rotation.sin_cos();
+        let transform = |v: Vec2| {
+            let v = if mirror { Vec2::new(v.x, -v.y) } else { v };
+            Vec2::new(cos * v.x - sin * v.y, sin * v.x + cos * v.y)
+        };
+
+        let mut bodies = self.bodies;
+        for body in &mut bodies {
+            body.pos = transform(body.pos - center) + self.position;
+            body.vel = transform(body.vel - drift) + self.velocity;
+        }
+        bodies
+    }
+}
+
+// Several systems merged into one set of bodies, such as two colliding galaxies
+#[derive(Default)]
+pub struct Scenario {
+    pub components: Vec<Component>,
+}
+
+impl Scenario {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn component(mut self, component: Component) -> Self {
+        self.components.push(component);
+        self
+    }
+
+    pub fn build(self) -> Vec<Body> {
+        self.components
+            .into_iter()
+            .flat_map(Component::place)
+            .collect()
+    }
+
+    // Two equal discs spinning the same way, falling straight into each other on a parabolic
+    // orbit from three disc radii apart
+    pub fn head_on(n: usize, seed: u64) -> Self {
+        let (a, b) = disc_pair(n, seed);
+        let separation = 3.0 * a.radius().max(b.radius());
+        let speed = parabolic_speed(&a, &b, separation);
+
+        let offset = Vec2::new(0.5 * separation, 0.0);
+        let velocity = Vec2::new(0.5 * speed, 0.0);
+
+        Self::new()
+            .component(a.position(-offset).velocity(velocity))
+            .component(b.position(offset).velocity(-velocity))
+    }
+
+    // Two equal discs spinning in opposite directions, passing on a parabolic orbit with an impact
+    // parameter of one disc radius so that their outer parts collide
+    pub fn grazing(n: usize, seed: u64) -> Self {
+        let (a, b) = disc_pair(n, seed);
+        let radius = a.radius().max(b.radius());
+        let offset = Vec2::new(1.5 * radius, 0.5 * radius);
+        let speed = parabolic_speed(&a, &b, 2.0 * offset.mag());
+
+        let velocity = Vec2::new(0.5 * speed, 0.0);
+
+        Self::new()
+            .component(
+                a.position(-offset)
+                    .velocity(velocity)
+                    .spin(Spin::Counterclockwise),
+            )
+            .component(
+                b.position(offset)
+                    .velocity(-velocity)
+                    .rotation(consts::FRAC_PI_3)
+                    .spin(Spin::Clockwise),
+            )
+    }
+}
+
+// Halves of `n` as two discs with different seeds, so that they aren't copies of each other
+fn disc_pair(n: usize, seed: u64) -> (Component, Component) {
+    let a = utils::uniform_disc(n / 2, seed);
+    let b = utils::uniform_disc(n - n / 2, seed.wrapping_add(1));
+    (Component::new(a), Component::new(b))
+}
+
+// Relative speed at `distance` of two point masses on a zero-energy orbit
+fn parabolic_speed(a: &Component, b: &Component, distance: Float) -> Float {
+    (2.0 * (a.mass() + b.mass()) / distance).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Center-of-mass position and velocity, and the angular momentum about them
+    fn motion(bodies: Vec<Body>) -> (Vec2, Vec2, Float) {
+        let component = Component::new(bodies);
+        let (center, drift) = component.center();
+        let angular_momentum = component.bodies.iter().fold(0.0, |sum, body| {
+            let (r, v) = (body.pos - center, body.vel - drift);
+            sum + body.mass * (r.x * v.y - r.y * v.x)
+        });
+        (center, drift, angular_momentum)
+    }
+
+    fn assert_close(a: Vec2, b: Vec2, scale: Float) {
+        assert!((a - b).mag() < 1e-4 * scale, "{a:?} vs {b:?}");
+    }
+
+    #[test]
+    fn components_are_moved_as_a_whole() {
+        let disc = utils::uniform_disc(1000, 1);
+        let (_, _, turning) = motion(disc.clone());
+        let radius = Component::new(disc.clone()).radius();
+
+        let position = Vec2::new(-300.0, 200.0);
+        let velocity = Vec2::new(5.0, -2.0);
+        for spin in [Spin::Clockwise, Spin::Counterclockwise] {
+            let component = Component::new(disc.clone())
+                .position(position)
+                .velocity(velocity)
+                .rotation(1.0)
+                .spin(spin);
+            let bodies = Scenario::new().component(component).build();
+
+            let (center, drift, angular_momentum) = motion(bodies.clone());
+            assert_close(center, position, radius);
+            assert_close(drift, velocity, velocity.mag());
+
+            // Rotating and mirroring change only the direction of the angular momentum
+            let expected = match spin {
+                Spin::Clockwise => -turning.abs(),
+                Spin::Counterclockwise => turning.abs(),
+            };
+            assert!((angular_momentum / expected - 1.0).abs() < 1e-3);
+            let placed = Component::new(bodies);
+            assert!((placed.radius() / radius - 1.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn presets_bring_two_discs_together() {
+        for (scenario, opposite_spins) in [
+            (Scenario::head_on(2001, 3), false),
+            (Scenario::grazing(2001, 3), true),
+        ] {
+            let [a, b] = [0, 1].map(|i| {
+                let component = &scenario.components[i];
+                (component.position, component.velocity, component.mass())
+            });
+            let bodies = scenario.build();
+            assert_eq!(bodies.len(), 2001);
+
+            // Both start at rest about their common center of mass, moving towards each other
+            let (center, drift, _) = motion(bodies.clone());
+            let separation = (b.0 - a.0).mag();
+            assert_close(center, Vec2::zero(), separation);
+            assert_close(drift, Vec2::zero(), a.1.mag());
+            assert!((b.0 - a.0).dot(b.1 - a.1) < 0.0);
+
+            let (_, _, spin_a) = motion(bodies[..1000].to_vec());
+            let (_, _, spin_b) = motion(bodies[1000..].to_vec());
+            assert_eq!(spin_a * spin_b < 0.0, opposite_spins);
+            assert!((a.2 / b.2 - 1.0).abs() < 1e-2, "masses {} and {}", a.2, b.2);
+        }
+    }
+}
@@ -1,87 +1,153 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+    sync::Arc,
+};
+
 use crate::{
     body::Body,
-    quadtree::{Quad, Quadtree},
-    utils,
+    config::{CollisionConfig, ConfigError, SimulationConfig},
+    diagnostics::Diagnostics,
+    float::{Float, Vec2},
+    integrator::{Integrator, System},
+    kernel::AdaptiveSoftening,
+    periodic::Periodic,
+    snapshot::{Snapshot, SnapshotError},
+    solver::GravitySolver,
 };
 
 use broccoli::aabb::Rect;
-use ultraviolet::Vec2;
 
 pub struct Simulation {
-    pub dt: f32,
+    pub dt: Float,
     pub frame: usize,
     pub bodies: Vec<Body>,
-    pub quadtree: Quadtree,
+    pub solver: Box<dyn GravitySolver>,
+    pub epsilon: Float,
+    pub adaptive_softening: AdaptiveSoftening,
+    pub softening_scale: Float,
+    pub collisions: CollisionConfig,
+    pub periodic: Option<Periodic>,
+    pub threads: usize,
+    pub integrator: Arc<dyn Integrator>,
+    // Parameters the simulation was built from, saved in snapshots with the current values of
+    // the fields above taking precedence
+    pub config: SimulationConfig,
 }
 
 impl Simulation {
     pub fn new() -> Self {
-        let dt = 0.05;
-        let n = 100000;
-        let theta = 1.0;
-        let epsilon = 1.0;
+        SimulationConfig::default()
+            .build()
+            .expect("the default config is valid")
+    }
 
-        let bodies: Vec<Body> = utils::uniform_disc(n);
-        let quadtree = Quadtree::new(theta, epsilon);
+    pub fn from_bodies(bodies: Vec<Body>, dt: Float, theta: Float, epsilon: Float) -> Self {
+        let config = SimulationConfig::default()
+            .dt(dt)
+            .theta(theta)
+            .epsilon(epsilon);
+
+        Self::from_config(bodies, &config).expect("the default config is valid")
+    }
 
-        Self {
-            dt,
+    pub fn from_config(bodies: Vec<Body>, config: &SimulationConfig) -> Result<Self, ConfigError> {
+        config.validate_for(2)?;
+
+        let mut simulation = Self {
+            dt: config.dt,
             frame: 0,
             bodies,
-            quadtree,
-        }
+            solver: config.solver.build(config),
+            epsilon: config.epsilon,
+            adaptive_softening: config.adaptive_softening,
+            softening_scale: config.softening_scale,
+            collisions: config.collisions,
+            periodic: config.periodic.build(),
+            threads: config.threads,
+            integrator: config.integrator.build(config.timesteps),
+            config: config.clone(),
+        };
+        simulation.wrap();
+        Ok(simulation)
     }
 
     pub fn step(&mut self) {
-        self.iterate();
-        self.collide();
-        self.attract();
+        let integrator = self.integrator.clone();
+        if self.frame == 0 {
+            integrator.start(self);
+        }
+        integrator.step(self);
         self.frame += 1;
     }
 
-    pub fn attract(&mut self) {
-        let quad = Quad::new_containing(&self.bodies);
-        self.quadtree.clear(quad);
+    pub fn diagnostics(&self) -> Diagnostics {
+        Diagnostics::measure(self)
+    }
 
-        for body in &self.bodies {
-            self.quadtree.insert(body.pos, body.mass);
+    // Potential at each body, from the solver of the last force evaluation or, before the first
+    // one, from a solver built just for the current positions
+    pub fn potentials(&self) -> Vec<Float> {
+        if self.bodies.is_empty() {
+            return Vec::new();
         }
-
-        self.quadtree.propagate();
-
-        for body in &mut self.bodies {
-            body.acc = self.quadtree.acc(body.pos);
+        if let Some(potentials) = self.solver.potentials(&self.bodies, self.threads) {
+            return potentials;
         }
+
+        let mut config = self.config.clone();
+        config.epsilon = self.epsilon;
+        config.reorder_bodies = false;
+        let mut solver = config.solver.build(&config);
+
+        let mut bodies = self.bodies.clone();
+        let (adaptive, scale) = (self.adaptive_softening, self.softening_scale);
+        adaptive.apply(&mut bodies, scale, self.threads);
+        solver.build(&mut bodies, self.threads);
+        solver
+            .potentials(&bodies, self.threads)
+            .expect("the solver was just built")
     }
 
-    pub fn iterate(&mut self) {
-        for body in &mut self.bodies {
-            body.update(self.dt);
+    pub fn snapshot(&self) -> Snapshot {
+        let mut config = self.config.clone();
+        config.dt = self.dt;
+        config.epsilon = self.epsilon;
+        config.adaptive_softening = self.adaptive_softening;
+        config.softening_scale = self.softening_scale;
+        config.collisions = self.collisions;
+        config.threads = self.threads;
+
+        Snapshot {
+            config,
+            frame: self.frame,
+            rng_state: fastrand::get_seed(),
+            bodies: self.bodies.clone(),
+            ids: Vec::new(),
         }
     }
 
-    pub fn collide(&mut self) {
-        let mut rects = self
-            .bodies
-            .iter()
-            .enumerate()
-            .map(|(index, body)| {
-                let pos = body.pos;
-                let radius = body.radius;
-                let min = pos - Vec2::one() * radius;
-                let max = pos + Vec2::one() * radius;
-                (Rect::new(min.x, max.x, min.y, max.y), index)
-            })
-            .collect::<Vec<_>>();
+    // Continues from `snapshot` so that the following steps are bit-for-bit the same as those of
+    // the simulation it was taken from. Also restores the random number generator of this thread.
+    pub fn from_snapshot(snapshot: Snapshot) -> Result<Self, ConfigError> {
+        fastrand::seed(snapshot.rng_state);
 
-        let mut broccoli = broccoli::Tree::new(&mut rects);
+        let mut simulation = Self::from_config(snapshot.bodies, &snapshot.config)?;
+        simulation.frame = snapshot.frame;
+        Ok(simulation)
+    }
 
-        broccoli.find_colliding_pairs(|i, j| {
-            let i = *i.unpack_inner();
-            let j = *j.unpack_inner();
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        let file = File::create(path)?;
+        self.snapshot().write(BufWriter::new(file))?;
+        Ok(())
+    }
 
-            self.resolve(i, j);
-        });
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SnapshotError> {
+        let file = File::open(path)?;
+        let snapshot = Snapshot::read(BufReader::new(file))?;
+        Ok(Self::from_snapshot(snapshot)?)
     }
 
     fn resolve(&mut self, i: usize, j: usize) {
@@ -94,7 +160,7 @@ impl Simulation {
         let r1 = b1.radius;
         let r2 = b2.radius;
 
-        let d = p2 - p1;
+        let d = self.displacement(p1, p2);
         let r = r1 + r2;
 
         if d.mag_sq() > r * r {
@@ -132,11 +198,11 @@ impl Simulation {
 
         let p1 = self.bodies[i].pos;
         let p2 = self.bodies[j].pos;
-        let d = p2 - p1;
+        let d = self.displacement(p1, p2);
         let d_dot_v = d.dot(v);
         let d_sq = d.mag_sq();
 
-        let tmp = d * (1.5 * d_dot_v / d_sq);
+        let tmp = d * ((1.0 + self.collisions.restitution) * d_dot_v / d_sq);
         let v1 = v1 + tmp * weight1;
         let v2 = v2 - tmp * weight2;
 
@@ -145,4 +211,116 @@ impl Simulation {
         self.bodies[i].pos += v1 * t;
         self.bodies[j].pos += v2 * t;
     }
+
+    // From `p1` to `p2`, or to its nearest image with periodic boundaries
+    fn displacement(&self, p1: Vec2, p2: Vec2) -> Vec2 {
+        match &self.periodic {
+            Some(periodic) => periodic.nearest(p2 - p1),
+            None => p2 - p1,
+        }
+    }
+}
+
+impl System for Simulation {
+    type Vector = Vec2;
+
+    fn dt(&self) -> Float {
+        self.dt
+    }
+
+    fn epsilon(&self) -> Float {
+        self.epsilon
+    }
+
+    fn bodies(&self) -> &[Body] {
+        &self.bodies
+    }
+
+    fn bodies_mut(&mut self) -> &mut [Body] {
+        &mut self.bodies
+    }
+
+    fn attract_where(&mut self, active: &(dyn Fn(&Body) -> bool + Sync)) {
+        let (adaptive, scale) = (self.adaptive_softening, self.softening_scale);
+        adaptive.apply(&mut self.bodies, scale, self.threads);
+
+        self.solver.build(&mut self.bodies, self.threads);
+        self.solver.attract(&mut self.bodies, active, self.threads);
+    }
+
+    fn collide(&mut self) {
+        if !self.collisions.enabled {
+            return;
+        }
+
+        let rect = |pos: Vec2, radius: Float| {
+            let min = pos - Vec2::one() * radius;
+            let max = pos + Vec2::one() * radius;
+            Rect::new(min.x, max.x, min.y, max.y)
+        };
+
+        let mut rects = self
+            .bodies
+            .iter()
+            .enumerate()
+            .map(|(index, body)| (rect(body.pos, body.radius), index))
+            .collect::<Vec<_>>();
+
+        // Bodies touching the edge of a periodic box get copies on the opposite side, so that
+        // they can collide with bodies there
+        if let Some(periodic) = &self.periodic {
+            let half = 0.5 * periodic.size();
+            for (index, body) in self.bodies.iter().enumerate() {
+                let (pos, radius) = (body.pos, body.radius);
+                let shift = |x: Float| {
+                    if x + radius > half {
+                        -periodic.size()
+                    } else if x - radius < -half {
+                        periodic.size()
+                    } else {
+                        0.0
+                    }
+                };
+
+                let (sx, sy) = (shift(pos.x), shift(pos.y));
+                let mut ghost = |offset: Vec2| rects.push((rect(pos + offset, radius), index));
+                if sx != 0.0 {
+                    ghost(Vec2::new(sx, 0.0));
+                }
+                if sy != 0.0 {
+                    ghost(Vec2::new(0.0, sy));
+                }
+                if sx != 0.0 && sy != 0.0 {
+                    ghost(Vec2::new(sx, sy));
+                }
+            }
+        }
+
+        let mut broccoli = broccoli::Tree::new(&mut rects);
+
+        broccoli.find_colliding_pairs(|i, j| {
+            let i = *i.unpack_inner();
+            let j = *j.unpack_inner();
+
+            if i != j {
+                self.resolve(i, j);
+            }
+        });
+
+        self.wrap();
+    }
+
+    fn wrap(&mut self) {
+        if let Some(periodic) = &self.periodic {
+            for body in &mut self.bodies {
+                body.wrap(periodic);
+            }
+        }
+    }
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        Self::new()
+    }
 }
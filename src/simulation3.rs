@@ -0,0 +1,112 @@
+use std::sync::Arc;
+
+use crate::{
+    body::Body3,
+    config::{ConfigError, SimulationConfig},
+    diagnostics::Diagnostics,
+    float::{Float, Vec3},
+    integrator::{Integrator, System},
+    octree::Octree,
+    parallel,
+};
+
+// 3D counterpart of `Simulation`, always using Barnes-Hut on an `Octree`. Configs asking for
+// anything else, such as another solver or collisions, are rejected by `SimulationConfig::validate`.
+pub struct Simulation3 {
+    pub dt: Float,
+    pub frame: usize,
+    pub bodies: Vec<Body3>,
+    pub octree: Octree,
+    pub epsilon: Float,
+    pub threads: usize,
+    pub integrator: Arc<dyn Integrator<Simulation3>>,
+}
+
+impl Simulation3 {
+    pub fn from_config(bodies: Vec<Body3>, config: &SimulationConfig) -> Result<Self, ConfigError> {
+        config.validate_for(3)?;
+
+        let mut octree = Octree::new(config.theta, config.epsilon);
+        octree.softening = config.softening;
+        octree.leaf_capacity = config.leaf_capacity.max(1);
+
+        Ok(Self {
+            dt: config.dt,
+            frame: 0,
+            bodies,
+            octree,
+            epsilon: config.epsilon,
+            threads: config.threads,
+            integrator: config.integrator.build(config.timesteps),
+        })
+    }
+
+    pub fn step(&mut self) {
+        let integrator = self.integrator.clone();
+        if self.frame == 0 {
+            integrator.start(self);
+        }
+        integrator.step(self);
+        self.frame += 1;
+    }
+
+    pub fn diagnostics(&self) -> Diagnostics {
+        Diagnostics::measure3(self)
+    }
+
+    // Potential at each body from the octree of the last force evaluation, or before the first
+    // one from an octree built just for the current positions
+    pub fn potentials(&self) -> Vec<Float> {
+        let bodies = &self.bodies;
+        let built;
+        let octree = if self.octree.nodes.is_empty() && !bodies.is_empty() {
+            let mut octree = self.octree.clone();
+            octree.build(bodies);
+            built = octree;
+            &built
+        } else {
+            &self.octree
+        };
+
+        let mut potentials = vec![0.0; bodies.len()];
+        parallel::for_each_chunk_indexed(&mut potentials, self.threads, 1024, |start, chunk| {
+            for (potential, body) in chunk.iter_mut().zip(&bodies[start..]) {
+                *potential = octree.potential_of(body);
+            }
+        });
+        potentials
+    }
+}
+
+impl System for Simulation3 {
+    type Vector = Vec3;
+
+    fn dt(&self) -> Float {
+        self.dt
+    }
+
+    fn epsilon(&self) -> Float {
+        self.epsilon
+    }
+
+    fn bodies(&self) -> &[Body3] {
+        &self.bodies
+    }
+
+    fn bodies_mut(&mut self) -> &mut [Body3] {
+        &mut self.bodies
+    }
+
+    fn attract_where(&mut self, active: &(dyn Fn(&Body3) -> bool + Sync)) {
+        self.octree.build(&self.bodies);
+
+        let octree = &self.octree;
+        parallel::for_each_chunk(&mut self.bodies, self.threads, 1024, |bodies| {
+            for body in bodies.iter_mut().filter(|body| active(body)) {
+                body.acc = octree.acc_of(body);
+            }
+        });
+    }
+
+    fn collide(&mut self) {}
+}
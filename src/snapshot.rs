@@ -0,0 +1,210 @@
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    mem,
+};
+
+use crate::{
+    body::Body,
+    config::{ConfigError, SimulationConfig},
+    float::{Float, Vec2},
+};
+
+const MAGIC: [u8; 8] = *b"BHSNAP\0\0";
+
+// Bumped whenever the layout below changes, older versions are rejected when reading
+pub const VERSION: u32 = 2;
+
+// Everything needed to continue a `Simulation` exactly where it left off. The binary layout is
+// little-endian throughout:
+//
+//   magic       8 bytes, "BHSNAP\0\0"
+//   version     u32
+//   float size  u32, 4 for f32 or 8 with the `f64` feature
+//   config      u64 length, then the `SimulationConfig` as UTF-8 TOML
+//   frame       u64
+//   rng state   u64
+//   body count  u64
+//   bodies      pos.x, pos.y, vel.x, vel.y, acc.x, acc.y, mass, radius, softening as floats,
+//               then level as u32
+//   id count    u64, 0 or the body count
+//   ids         u64 each
+pub struct Snapshot {
+    pub config: SimulationConfig,
+    pub frame: usize,
+    // State of the thread-local `fastrand` generator
+    pub rng_state: u64,
+    pub bodies: Vec<Body>,
+    // Catalog ids of the bodies if they were imported, otherwise empty
+    pub ids: Vec<u64>,
+}
+
+impl Snapshot {
+    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(mem::size_of::<Float>() as u32).to_le_bytes())?;
+
+        let config = self.config.to_toml();
+        writer.write_all(&(config.len() as u64).to_le_bytes())?;
+        writer.write_all(config.as_bytes())?;
+
+        writer.write_all(&(self.frame as u64).to_le_bytes())?;
+        writer.write_all(&self.rng_state.to_le_bytes())?;
+        writer.write_all(&(self.bodies.len() as u64).to_le_bytes())?;
+
+        for body in &self.bodies {
+            let floats = [
+                body.pos.x,
+                body.pos.y,
+                body.vel.x,
+                body.vel.y,
+                body.acc.x,
+                body.acc.y,
+                body.mass,
+                body.radius,
+                body.softening,
+            ];
+            for value in floats {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+            writer.write_all(&body.level.to_le_bytes())?;
+        }
+
+        writer.write_all(&(self.ids.len() as u64).to_le_bytes())?;
+        for id in &self.ids {
+            writer.write_all(&id.to_le_bytes())?;
+        }
+
+        writer.flush()
+    }
+
+    pub fn read(mut reader: impl Read) -> Result<Self, SnapshotError> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(SnapshotError::NotASnapshot);
+        }
+
+        let version = read_u32(&mut reader)?;
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let float_size = read_u32(&mut reader)?;
+        if float_size as usize != mem::size_of::<Float>() {
+            return Err(SnapshotError::Precision(float_size));
+        }
+
+        let mut config = vec![0; read_u64(&mut reader)? as usize];
+        reader.read_exact(&mut config)?;
+        let config = String::from_utf8(config)
+            .map_err(|_| SnapshotError::Corrupt("config is not valid UTF-8".to_string()))?;
+        let config = SimulationConfig::from_toml(&config)?;
+
+        let frame = read_u64(&mut reader)? as usize;
+        let rng_state = read_u64(&mut reader)?;
+        let count = read_u64(&mut reader)? as usize;
+
+        // Capped so that a corrupt count fails on the first missing body rather than allocating
+        let mut bodies = Vec::with_capacity(count.min(1 << 20));
+        for _ in 0..count {
+            let mut floats = [0.0; 9];
+            for value in &mut floats {
+                *value = read_float(&mut reader)?;
+            }
+            let [px, py, vx, vy, ax, ay, mass, radius, softening] = floats;
+
+            let mut body = Body::new(Vec2::new(px, py), Vec2::new(vx, vy), mass, radius);
+            body.acc = Vec2::new(ax, ay);
+            body.softening = softening;
+            body.level = read_u32(&mut reader)?;
+            bodies.push(body);
+        }
+
+        let id_count = read_u64(&mut reader)? as usize;
+        if id_count != 0 && id_count != count {
+            return Err(SnapshotError::Corrupt(format!(
+                "{id_count} ids for {count} bodies"
+            )));
+        }
+        let ids = (0..id_count)
+            .map(|_| read_u64(&mut reader))
+            .collect::<io::Result<_>>()?;
+
+        Ok(Self {
+            config,
+            frame,
+            rng_state,
+            bodies,
+            ids,
+        })
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_float(reader: &mut impl Read) -> io::Result<Float> {
+    let mut bytes = [0; mem::size_of::<Float>()];
+    reader.read_exact(&mut bytes)?;
+    Ok(Float::from_le_bytes(bytes))
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    NotASnapshot,
+    UnsupportedVersion(u32),
+    // Size in bytes of the floats in the file, which was written with the other precision
+    Precision(u32),
+    Config(ConfigError),
+    Corrupt(String),
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<ConfigError> for SnapshotError {
+    fn from(e: ConfigError) -> Self {
+        Self::Config(e)
+    }
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                write!(f, "snapshot ends unexpectedly")
+            }
+            Self::Io(e) => write!(f, "failed to access snapshot: {e}"),
+            Self::NotASnapshot => write!(f, "not a snapshot file"),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported snapshot version {version}, expected {VERSION}"
+            ),
+            Self::Precision(size) => write!(
+                f,
+                "snapshot was written with {}-bit floats, build with{} the `f64` feature to load it",
+                size * 8,
+                if *size == 8 { "" } else { "out" }
+            ),
+            Self::Config(e) => write!(f, "invalid config in snapshot: {e}"),
+            Self::Corrupt(reason) => write!(f, "corrupt snapshot: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
@@ -0,0 +1,198 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    body::Body,
+    config::SimulationConfig,
+    direct::DirectSum,
+    float::Float,
+    fmm::Fmm,
+    parallel,
+    quadtree::{Construction, Node, Quadtree},
+};
+
+// A method of computing gravitational accelerations, rebuilt from the bodies before every evaluation
+pub trait GravitySolver: Send + Sync {
+    // May reorder `bodies`, but never changes anything else about them
+    fn build(&mut self, bodies: &mut [Body], threads: usize);
+
+    // Sets `acc` of the bodies matching `active`, from the state at the last `build`
+    fn attract(&self, bodies: &mut [Body], active: &(dyn Fn(&Body) -> bool + Sync), threads: usize);
+
+    // Potential at each body, excluding the body itself, or `None` before the first `build`
+    fn potentials(&self, bodies: &[Body], threads: usize) -> Option<Vec<Float>>;
+
+    // Tree nodes for visualization, if the solver has any
+    fn nodes(&self) -> &[Node] {
+        &[]
+    }
+}
+
+pub struct BarnesHut {
+    pub quadtree: Quadtree,
+    pub construction: Construction,
+    pub reorder_bodies: bool,
+}
+
+impl BarnesHut {
+    pub fn new(quadtree: Quadtree, construction: Construction, reorder_bodies: bool) -> Self {
+        Self {
+            quadtree,
+            construction,
+            reorder_bodies,
+        }
+    }
+
+    // Puts the bodies in the same order as the quadtree's points
+    fn reorder(&mut self, bodies: &mut [Body]) {
+        let sorted = self
+            .quadtree
+            .points
+            .iter()
+            .map(|p| bodies[p.index])
+            .collect::<Vec<_>>();
+        bodies.copy_from_slice(&sorted);
+
+        for (i, p) in self.quadtree.points.iter_mut().enumerate() {
+            p.index = i;
+        }
+    }
+}
+
+impl GravitySolver for BarnesHut {
+    fn build(&mut self, bodies: &mut [Body], threads: usize) {
+        match self.construction {
+            Construction::Morton => {
+                self.quadtree.build_morton_parallel(bodies, threads);
+                if self.reorder_bodies {
+                    self.reorder(bodies);
+                }
+            }
+            Construction::Insert => self.quadtree.build_parallel(bodies, threads),
+        }
+    }
+
+    fn attract(
+        &self,
+        bodies: &mut [Body],
+        active: &(dyn Fn(&Body) -> bool + Sync),
+        threads: usize,
+    ) {
+        let quadtree = &self.quadtree;
+        parallel::for_each_chunk(bodies, threads, 1024, |bodies| {
+            for body in bodies.iter_mut().filter(|body| active(body)) {
+                body.acc = quadtree.acc_of(body);
+            }
+        });
+    }
+
+    fn potentials(&self, bodies: &[Body], threads: usize) -> Option<Vec<Float>> {
+        if self.quadtree.nodes.is_empty() {
+            return None;
+        }
+
+        let quadtree = &self.quadtree;
+        let mut potentials = vec![0.0; bodies.len()];
+        parallel::for_each_chunk_indexed(&mut potentials, threads, 1024, |start, chunk| {
+            for (potential, body) in chunk.iter_mut().zip(&bodies[start..]) {
+                *potential = quadtree.potential_of(body);
+            }
+        });
+        Some(potentials)
+    }
+
+    fn nodes(&self) -> &[Node] {
+        &self.quadtree.nodes
+    }
+}
+
+impl GravitySolver for DirectSum {
+    fn build(&mut self, bodies: &mut [Body], _threads: usize) {
+        self.set_bodies(bodies);
+    }
+
+    fn attract(
+        &self,
+        bodies: &mut [Body],
+        active: &(dyn Fn(&Body) -> bool + Sync),
+        threads: usize,
+    ) {
+        parallel::for_each_chunk(bodies, threads, 64, |bodies| {
+            for body in bodies.iter_mut().filter(|body| active(body)) {
+                body.acc = self.acc_of(body);
+            }
+        });
+    }
+
+    fn potentials(&self, bodies: &[Body], threads: usize) -> Option<Vec<Float>> {
+        if self.points.is_empty() {
+            return None;
+        }
+
+        let mut potentials = vec![0.0; bodies.len()];
+        parallel::for_each_chunk_indexed(&mut potentials, threads, 64, |start, chunk| {
+            for (potential, body) in chunk.iter_mut().zip(&bodies[start..]) {
+                *potential = self.potential_of(body);
+            }
+        });
+        Some(potentials)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SolverKind {
+    #[default]
+    BarnesHut,
+    Direct,
+    Fmm,
+}
+
+impl SolverKind {
+    // Expects a config that passed `SimulationConfig::validate`
+    pub fn build(self, config: &SimulationConfig) -> Box<dyn GravitySolver> {
+        match self {
+            Self::BarnesHut => {
+                let mut quadtree = Quadtree::new(config.theta, config.epsilon);
+                quadtree.softening = config.softening;
+                quadtree.opening = config.opening;
+                quadtree.alpha = config.alpha;
+                quadtree.box_guard = config.box_guard.unwrap_or(config.opening.guarded());
+                quadtree.multipole = config.multipole;
+                quadtree.leaf_capacity = config.leaf_capacity.max(1);
+                quadtree.periodic = config.periodic.build();
+
+                Box::new(BarnesHut::new(
+                    quadtree,
+                    config.construction,
+                    config.reorder_bodies,
+                ))
+            }
+            Self::Direct => {
+                let mut direct = DirectSum::new(config.epsilon);
+                direct.softening = config.softening;
+                direct.periodic = config.periodic.build();
+                Box::new(direct)
+            }
+            Self::Fmm => {
+                let mut fmm = Fmm::new(config.epsilon, config.fmm);
+                fmm.quadtree.softening = config.softening;
+                Box::new(fmm)
+            }
+        }
+    }
+}
+
+impl FromStr for SolverKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "barnes_hut" | "bh" => Ok(Self::BarnesHut),
+            "direct" => Ok(Self::Direct),
+            "fmm" => Ok(Self::Fmm),
+            _ => Err(format!("unknown solver '{s}'")),
+        }
+    }
+}
@@ -1,41 +1,293 @@
-use crate::body::Body;
-use ultraviolet::Vec2;
+use crate::{
+    body::{Body, Body3},
+    config::DiscConfig,
+    float::{self, consts, widen, Float, Vec2, Vec3},
+    models,
+    quadtree::Quadtree,
+};
 
-pub fn uniform_disc(n: usize) -> Vec<Body> {
-    fastrand::seed(0);
+pub fn uniform_disc(n: usize, seed: u64) -> Vec<Body> {
+    fastrand::seed(seed);
     let inner_radius = 25.0;
-    let outer_radius = (n as f32).sqrt() * 5.0;
+    let outer_radius = (n as Float).sqrt() * 5.0;
 
     let mut bodies: Vec<Body> = Vec::with_capacity(n);
 
     let m = 1e6;
-    let center = Body::new(Vec2::zero(), Vec2::zero(), m as f32, inner_radius);
+    let center = Body::new(Vec2::zero(), Vec2::zero(), m as Float, inner_radius);
     bodies.push(center);
 
     while bodies.len() < n {
-        let a = fastrand::f32() * std::f32::consts::TAU;
+        let a = float::random() * consts::TAU;
         let (sin, cos) = a.sin_cos();
         let t = inner_radius / outer_radius;
-        let r = fastrand::f32() * (1.0 - t * t) + t * t;
+        let r = float::random() * (1.0 - t * t) + t * t;
         let pos = Vec2::new(cos, sin) * outer_radius * r.sqrt();
         let vel = Vec2::new(sin, -cos);
-        let mass = 1.0f32;
+        let mass: Float = 1.0;
         let radius = mass.cbrt();
 
         bodies.push(Body::new(pos, vel, mass, radius));
     }
 
     bodies.sort_by(|a, b| a.pos.mag_sq().total_cmp(&b.pos.mag_sq()));
+    let enclosed = enclosed_mass(&bodies);
+    for (body, mass) in bodies.iter_mut().zip(enclosed) {
+        if body.pos == Vec2::zero() {
+            continue;
+        }
+
+        let v = (mass / body.pos.mag()).sqrt();
+        body.vel *= v;
+    }
+
+    bodies
+}
+
+// Mass within the radius of each body, including the body itself, which sets the speed of a
+// circular orbit at that radius
+fn enclosed_mass(bodies: &[Body]) -> Vec<Float> {
+    let mut order = (0..bodies.len()).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| bodies[a].pos.mag_sq().total_cmp(&bodies[b].pos.mag_sq()));
+
+    let mut enclosed = vec![0.0; bodies.len()];
     let mut mass = 0.0;
-    for i in 0..n {
+    for i in order {
         mass += bodies[i].mass;
-        if bodies[i].pos == Vec2::zero() {
+        enclosed[i] = mass;
+    }
+    enclosed
+}
+
+// Disc with exponential surface density out to ten scale lengths, rotating the same way as
+// `uniform_disc`, around an optional bulge from `models::hernquist`. The rotation speed comes
+// from the enclosed mass as in `uniform_disc`, less the asymmetric drift, with a radial
+// dispersion `σ_R = 3.36 Q Σ / κ` and the epicyclic ratio `σ_φ / σ_R = κ / 2Ω`. All bodies have
+// the same mass.
+pub fn exponential_disc(n: usize, config: &DiscConfig, seed: u64) -> Vec<Body> {
+    let DiscConfig {
+        mass: disc_mass,
+        scale_length: h,
+        bulge_mass,
+        bulge_radius: a,
+        toomre_q,
+    } = *config;
+    let total = disc_mass + bulge_mass;
+    let body_mass = total / n.max(1) as Float;
+
+    let n_bulge = if bulge_mass > 0.0 {
+        ((n as Float * bulge_mass / total).round() as usize).min(n)
+    } else {
+        0
+    };
+    let mut bodies = if n_bulge > 0 {
+        models::hernquist(
+            n_bulge,
+            n_bulge as Float * body_mass,
+            a,
+            seed.wrapping_add(1),
+        )
+    } else {
+        Vec::new()
+    };
+
+    fastrand::seed(seed);
+    while bodies.len() < n {
+        // `R e^(-R / h)` is a gamma distribution, the sum of two exponentials
+        let r = -h * ((1.0 - float::random()) * (1.0 - float::random())).ln();
+        if r > 10.0 * h {
+            continue;
+        }
+
+        let (sin, cos) = (float::random() * consts::TAU).sin_cos();
+        let pos = Vec2::new(cos, sin) * r;
+        bodies.push(Body::new(pos, Vec2::zero(), body_mass, body_mass.cbrt()));
+    }
+
+    // The dispersion needs the derivative of the mass profile, so it uses the smooth profile
+    // rather than the sampled one
+    let surface = |r: Float| disc_surface(config, r);
+    let kappa_sq = |r: Float| disc_kappa_sq(config, r);
+
+    let enclosed = enclosed_mass(&bodies);
+    for (body, mass) in bodies.iter_mut().zip(enclosed).skip(n_bulge) {
+        let r = body.pos.mag();
+        if r == 0.0 {
+            continue;
+        }
+
+        let omega_sq = mass / (r * r * r);
+        let kappa = kappa_sq(r).sqrt();
+        let sigma_r = 3.36 * toomre_q * surface(r) / kappa;
+        let sigma_phi = sigma_r * (0.25 * kappa * kappa / omega_sq).sqrt();
+
+        // Asymmetric drift from the radial Jeans equation, with `Σ σ_R² ∝ Σ³ / κ²`
+        let step = 1e-3;
+        let slope_kappa =
+            (kappa_sq(r * (1.0 + step)) / kappa_sq(r * (1.0 - step))).ln() / (2.0 * step);
+        let slope = -3.0 * r / h - slope_kappa;
+        let ratio = sigma_phi * sigma_phi / (sigma_r * sigma_r);
+        let v_sq = mass / r - sigma_r * sigma_r * (ratio - 1.0 - slope);
+        let v_phi = v_sq.max(0.0).sqrt();
+
+        let radial = body.pos / r;
+        let tangent = Vec2::new(radial.y, -radial.x);
+        body.vel = tangent * (v_phi + sigma_phi * gaussian()) + radial * (sigma_r * gaussian());
+    }
+
+    // `models::hernquist` puts the bulge in virial equilibrium in its own potential, so its speeds
+    // are scaled again to satisfy `2K = -Σ m r·a` with the forces of the disc included
+    if n_bulge > 0 {
+        let mut quadtree = Quadtree::new(0.7, 0.0);
+        quadtree.build(&bodies);
+
+        let mut kinetic = 0.0;
+        let mut virial = 0.0;
+        for body in &bodies[..n_bulge] {
+            kinetic += 0.5 * widen(body.mass) * widen(body.vel.mag_sq());
+            virial += widen(body.mass) * widen(body.pos.dot(quadtree.acc_of(body)));
+        }
+
+        if kinetic > 0.0 && virial < 0.0 {
+            let scale = (-virial / (2.0 * kinetic)).sqrt() as Float;
+            for body in &mut bodies[..n_bulge] {
+                body.vel *= scale;
+            }
+        }
+    }
+
+    // Remove the bulk motion left over from sampling
+    let drift = bodies.iter().fold(Vec2::zero(), |sum, body| sum + body.vel) / n.max(1) as Float;
+    for body in &mut bodies {
+        body.vel -= drift;
+    }
+
+    bodies
+}
+
+// Surface density of the smooth profile that `exponential_disc` samples
+fn disc_surface(config: &DiscConfig, r: Float) -> Float {
+    let h = config.scale_length;
+    config.mass / (consts::TAU * h * h) * (-r / h).exp()
+}
+
+// Epicyclic frequency `κ² = 4Ω² + R dΩ²/dR` of the smooth disc and bulge, with `Ω² = M(R) / R³`
+// from the mass they enclose
+fn disc_kappa_sq(config: &DiscConfig, r: Float) -> Float {
+    let (h, bulge_mass, a) = (config.scale_length, config.bulge_mass, config.bulge_radius);
+    let x = r / h;
+    let disc = config.mass * (1.0 - (1.0 + x) * (-x).exp());
+    let bulge = bulge_mass * r * r / ((r + a) * (r + a));
+    let derivative =
+        consts::TAU * r * disc_surface(config, r) + 2.0 * bulge_mass * a * r / (r + a).powi(3);
+    (disc + bulge) / (r * r * r) + derivative / (r * r)
+}
+
+// Uniform-density ball with isotropic Gaussian velocities, with the dispersion `σ² = M / 5R` per
+// axis that puts an unsoftened uniform sphere in virial equilibrium
+pub fn uniform_sphere(n: usize, seed: u64) -> Vec<Body3> {
+    fastrand::seed(seed);
+    let radius = (n as Float).cbrt() * 5.0;
+    let mass: Float = 1.0;
+    let sigma = (n as Float * mass / (5.0 * radius)).sqrt();
+
+    let mut bodies = Vec::with_capacity(n);
+    while bodies.len() < n {
+        let pos = Vec3::new(float::random(), float::random(), float::random()) * 2.0 - Vec3::one();
+        if pos.mag_sq() > 1.0 {
             continue;
         }
 
-        let v = (mass / bodies[i].pos.mag()).sqrt();
-        bodies[i].vel *= v;
+        let vel = Vec3::new(gaussian(), gaussian(), gaussian()) * sigma;
+        bodies.push(Body3::new(pos * radius, vel, mass, mass.cbrt()));
+    }
+
+    // Remove the bulk motion left over from sampling
+    let drift = bodies.iter().fold(Vec3::zero(), |sum, body| sum + body.vel) / n.max(1) as Float;
+    for body in &mut bodies {
+        body.vel -= drift;
     }
 
     bodies
 }
+
+// Standard normal, from the Box-Muller transform
+fn gaussian() -> Float {
+    let u = 1.0 - float::random();
+    let v = float::random() * consts::TAU;
+    (-2.0 * u.ln()).sqrt() * v.cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Measures `Q = σ_R κ / 3.36 Σ` of the disc bodies in annuli between half a scale length and
+    // four, with `κ` from the mass profile the velocities were set up with and from the forces of
+    // the sampled bodies
+    #[test]
+    fn disc_has_the_target_toomre_q() {
+        let config = DiscConfig::default();
+        let n = 50000;
+        let bodies = exponential_disc(n, &config, 4);
+        let n_bulge = (n as Float * config.bulge_mass / (config.mass + config.bulge_mass)).round();
+        let disc = &bodies[n_bulge as usize..];
+
+        let mut quadtree = Quadtree::new(0.5, 1.0);
+        quadtree.build(&bodies);
+
+        // Radius, radial dispersion, surface density and `Ω²` from the forces in each annulus
+        let h = widen(config.scale_length);
+        let edges = [0.5, 0.75, 1.0, 1.25, 1.5, 1.75, 2.0, 2.5, 3.0, 3.5, 4.0];
+        let annuli = edges
+            .windows(2)
+            .map(|edges| {
+                let (inner, outer) = (edges[0] * h, edges[1] * h);
+                let (mut count, mut sum, mut sum_sq, mut omega_sq) = (0.0, 0.0, 0.0, 0.0);
+                for body in disc {
+                    let r = widen(body.pos.mag());
+                    if r < inner || r >= outer {
+                        continue;
+                    }
+                    let radial = body.pos / body.pos.mag();
+                    let v = widen(body.vel.dot(radial));
+                    count += 1.0;
+                    sum += v;
+                    sum_sq += v * v;
+                    omega_sq -= widen(quadtree.acc_of(body).dot(radial)) / r;
+                }
+
+                let sigma = (sum_sq / count - (sum / count).powi(2)).sqrt();
+                let area = std::f64::consts::PI * (outer * outer - inner * inner);
+                let surface = count * widen(disc[0].mass) / area;
+                (0.5 * (inner + outer), sigma, surface, omega_sq / count)
+            })
+            .collect::<Vec<_>>();
+
+        let target = widen(config.toomre_q);
+        for window in annuli.windows(3) {
+            let [(r_in, ..), (r, sigma, surface, omega_sq), (r_out, ..)] = *window else {
+                unreachable!();
+            };
+            let q = |kappa_sq: f64| sigma * kappa_sq.sqrt() / (3.36 * surface);
+
+            let kappa_sq = widen(disc_kappa_sq(&config, r as Float));
+            assert!(
+                (q(kappa_sq) / target - 1.0).abs() < 0.05,
+                "{} at {r}",
+                q(kappa_sq)
+            );
+
+            // The forces in a flat disc are stronger than those of the enclosed mass as a sphere,
+            // and its `κ` larger by up to a quarter out to a few scale lengths
+            let slope = (window[2].3 - window[0].3) / (r_out / r_in).ln();
+            let kappa_sq = 4.0 * omega_sq + slope;
+            let ratio = q(kappa_sq) / target;
+            assert!(
+                (0.95..1.3).contains(&ratio),
+                "{} at {r} from the forces",
+                q(kappa_sq)
+            );
+        }
+    }
+}
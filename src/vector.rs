@@ -0,0 +1,76 @@
+use std::ops::{Add, AddAssign, Mul, MulAssign, Sub, SubAssign};
+
+use ultraviolet::DVec3;
+
+use crate::float::{widen, Float, Vec2, Vec3};
+
+// The vector operations bodies and integrators need, so that they work in both 2D and 3D
+pub trait Vector:
+    Copy
+    + Send
+    + Sync
+    + PartialEq
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Float, Output = Self>
+    + AddAssign
+    + SubAssign
+    + MulAssign<Float>
+    + 'static
+{
+    const DIMENSIONS: usize;
+
+    fn zero() -> Self;
+
+    fn mag_sq(&self) -> Float;
+
+    fn mag(&self) -> Float {
+        self.mag_sq().sqrt()
+    }
+
+    // Widened to double precision, with `z = 0` in 2D
+    fn to_dvec3(self) -> DVec3;
+
+    // Also with `z = 0` in 2D, so only the first `DIMENSIONS` are meaningful
+    fn components(self) -> [Float; 3];
+}
+
+impl Vector for Vec2 {
+    const DIMENSIONS: usize = 2;
+
+    fn zero() -> Self {
+        Vec2::zero()
+    }
+
+    fn mag_sq(&self) -> Float {
+        Vec2::mag_sq(self)
+    }
+
+    fn to_dvec3(self) -> DVec3 {
+        DVec3::new(widen(self.x), widen(self.y), 0.0)
+    }
+
+    fn components(self) -> [Float; 3] {
+        [self.x, self.y, 0.0]
+    }
+}
+
+impl Vector for Vec3 {
+    const DIMENSIONS: usize = 3;
+
+    fn zero() -> Self {
+        Vec3::zero()
+    }
+
+    fn mag_sq(&self) -> Float {
+        Vec3::mag_sq(self)
+    }
+
+    fn to_dvec3(self) -> DVec3 {
+        DVec3::new(widen(self.x), widen(self.y), widen(self.z))
+    }
+
+    fn components(self) -> [Float; 3] {
+        [self.x, self.y, self.z]
+    }
+}
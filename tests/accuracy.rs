@@ -0,0 +1,171 @@
+use barnes_hut::{
+    direct::DirectSum,
+    float::{widen, Float},
+    models, utils, Body, Body3, Fmm, FmmConfig, GravitySolver, Octree, Opening, SimulationConfig,
+    Softening,
+};
+
+// Accelerations and potentials that `solver` finds for `bodies`
+fn evaluate(solver: &mut dyn GravitySolver, bodies: &[Body]) -> (Vec<Body>, Vec<f64>) {
+    let mut bodies = bodies.to_vec();
+    solver.build(&mut bodies, 1);
+    solver.attract(&mut bodies, &|_| true, 1);
+    let potentials = solver.potentials(&bodies, 1).unwrap();
+    (bodies, potentials.into_iter().map(widen).collect())
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    values.sort_by(f64::total_cmp);
+    values[values.len() / 2]
+}
+
+// Relative errors of the acceleration and potential of each body from `solver` against direct
+// summation
+fn errors(solver: &mut dyn GravitySolver, bodies: &[Body], epsilon: Float) -> (Vec<f64>, Vec<f64>) {
+    let mut direct = DirectSum::new(epsilon);
+    direct.softening = Softening::Plummer;
+    let (exact, exact_potentials) = evaluate(&mut direct, bodies);
+    let (approx, potentials) = evaluate(solver, bodies);
+
+    let acc = exact
+        .iter()
+        .zip(&approx)
+        .map(|(exact, approx)| widen((approx.acc - exact.acc).mag() / exact.acc.mag()))
+        .collect();
+    let potential = exact_potentials
+        .iter()
+        .zip(&potentials)
+        .map(|(exact, approx)| ((approx - exact) / exact).abs())
+        .collect();
+    (acc, potential)
+}
+
+#[test]
+fn fmm_converges_to_direct_summation_with_its_order() {
+    let epsilon = 1.0;
+    let bodies = models::plummer(2000, 100000.0, 300.0, 11);
+
+    let mut last = (f64::INFINITY, f64::INFINITY);
+    for (order, acc_bound, potential_bound) in [(2, 2e-2, 1e-3), (4, 1e-3, 5e-5), (6, 1e-4, 2e-5)] {
+        let config = FmmConfig {
+            order,
+            ..FmmConfig::default()
+        };
+        let mut fmm = Fmm::new(epsilon, config);
+        let (acc, potential) = errors(&mut fmm, &bodies, epsilon);
+        let (acc, potential) = (median(acc), median(potential));
+
+        assert!(acc < acc_bound, "order {order}: acceleration error {acc:e}");
+        assert!(
+            potential < potential_bound,
+            "order {order}: potential error {potential:e}"
+        );
+        assert!(acc < last.0 && potential < last.1, "order {order}");
+        last = (acc, potential);
+    }
+}
+
+// Median and maximum acceleration errors of each opening criterion at `theta = 1`
+#[test]
+fn opening_criteria_against_direct_summation() {
+    let epsilon = 1.0;
+    // `Opening::Relative` compares against the previous accelerations, taken to be the exact ones
+    let (bodies, _) = evaluate(&mut DirectSum::new(epsilon), &utils::uniform_disc(5000, 3));
+    let acc_errors = |config: &SimulationConfig| {
+        let mut solver = config.solver.build(config);
+        let (acc, _) = errors(&mut *solver, &bodies, epsilon);
+        let max = acc.iter().copied().fold(0.0, f64::max);
+        (median(acc), max)
+    };
+
+    for (opening, median_bound, max_bound) in [
+        (Opening::Geometric, 1e-3, 0.3),
+        (Opening::Bmax, 1e-3, 0.3),
+        (Opening::Relative, 2e-3, 3e-2),
+    ] {
+        let config = SimulationConfig::new()
+            .theta(1.0)
+            .epsilon(epsilon)
+            .softening(Softening::Plummer)
+            .opening(opening);
+        let (median, max) = acc_errors(&config);
+        assert!(
+            median < median_bound,
+            "{opening:?}: median error {median:e}"
+        );
+        assert!(max < max_bound, "{opening:?}: maximum error {max:e}");
+
+        // The box guard is on by default for the criteria whose worst errors it cuts down
+        if opening.guarded() {
+            let (_, unguarded) = acc_errors(&config.box_guard(false));
+            assert!(max < unguarded, "{opening:?}: {max:e} with the box guard");
+        }
+    }
+}
+
+// Accelerations and potentials of every body from every other, summed directly in double precision
+fn direct3(bodies: &[Body3], epsilon: f64) -> Vec<([f64; 3], f64)> {
+    bodies
+        .iter()
+        .map(|body| {
+            let (mut acc, mut potential) = ([0.0; 3], 0.0);
+            for other in bodies {
+                let d = other.pos - body.pos;
+                let d = [d.x, d.y, d.z].map(widen);
+                let r = (d.iter().map(|x| x * x).sum::<f64>() + epsilon * epsilon).sqrt();
+                let mass = widen(other.mass);
+                for (acc, d) in acc.iter_mut().zip(d) {
+                    *acc += mass * d / (r * r * r);
+                }
+                if other.pos != body.pos {
+                    potential -= mass / r;
+                }
+            }
+            (acc, potential)
+        })
+        .collect()
+}
+
+#[test]
+fn octree_converges_to_direct_summation_with_theta() {
+    let epsilon = 1.0;
+    let bodies = models::plummer3(2000, 100000.0, 300.0, 11);
+    let exact = direct3(&bodies, widen(epsilon));
+
+    let mut last = (f64::INFINITY, f64::INFINITY);
+    for (theta, acc_bound, potential_bound) in
+        [(1.0, 3e-2, 2e-3), (0.5, 5e-3, 5e-4), (0.25, 1e-3, 1e-4)]
+    {
+        let mut octree = Octree::new(theta, epsilon);
+        octree.softening = Softening::Plummer;
+        octree.build(&bodies);
+
+        let (acc, potential): (Vec<_>, Vec<_>) = bodies
+            .iter()
+            .zip(&exact)
+            .map(|(body, (exact_acc, exact_potential))| {
+                let acc = octree.acc_of(body);
+                let error = [acc.x, acc.y, acc.z]
+                    .iter()
+                    .zip(exact_acc)
+                    .map(|(&acc, exact)| (widen(acc) - exact).powi(2))
+                    .sum::<f64>();
+                let exact_mag = exact_acc.iter().map(|x| x * x).sum::<f64>();
+                let potential = widen(octree.potential_of(body));
+                (
+                    (error / exact_mag).sqrt(),
+                    ((potential - exact_potential) / exact_potential).abs(),
+                )
+            })
+            .unzip();
+        let (acc, potential) = (median(acc), median(potential));
+
+        assert!(acc < acc_bound, "theta {theta}: acceleration error {acc:e}");
+        assert!(
+            potential < potential_bound,
+            "theta {theta}: potential error {potential:e}"
+        );
+        assert!(acc < last.0 && potential < last.1, "theta {theta}");
+        last = (acc, potential);
+    }
+}
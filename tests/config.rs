@@ -0,0 +1,93 @@
+use barnes_hut::{
+    integrator::MAX_LEVEL, AdaptiveSoftening, CollisionConfig, ConfigError, InitialConditions,
+    IntegratorKind, Multipole, Opening, PeriodicConfig, SimulationConfig, SolverKind,
+    TimestepConfig,
+};
+
+// Configs put together in code are checked when they are built, rather than panicking later
+fn check_invalid(config: SimulationConfig, reason: &str) {
+    let Err(ConfigError::Invalid(message)) = config.validate() else {
+        panic!("expected '{reason}' to be rejected");
+    };
+    assert!(message.contains(reason), "{message}");
+
+    let built = if config.dimensions == 3 {
+        config.build3().err()
+    } else {
+        config.build().err()
+    };
+    assert!(matches!(built, Some(ConfigError::Invalid(_))));
+}
+
+#[test]
+fn periodic_boundaries_need_a_supporting_solver() {
+    let periodic = PeriodicConfig {
+        enabled: true,
+        ..PeriodicConfig::default()
+    };
+    let config = SimulationConfig::new().n(10).periodic(periodic);
+    assert!(config.build().is_ok());
+
+    check_invalid(config.clone().solver(SolverKind::Fmm), "periodic");
+    check_invalid(
+        config.periodic(PeriodicConfig {
+            size: 0.0,
+            ..periodic
+        }),
+        "periodic.size",
+    );
+}
+
+#[test]
+fn block_timesteps_have_a_deepest_level() {
+    let timesteps = |max_level| TimestepConfig {
+        max_level,
+        ..TimestepConfig::default()
+    };
+    let config = SimulationConfig::new()
+        .n(10)
+        .integrator(IntegratorKind::BlockLeapfrog);
+
+    assert!(config
+        .clone()
+        .timesteps(timesteps(MAX_LEVEL))
+        .build()
+        .is_ok());
+    check_invalid(config.timesteps(timesteps(MAX_LEVEL + 1)), "max_level");
+}
+
+#[test]
+fn three_dimensions_only_support_barnes_hut_without_collisions() {
+    let config = SimulationConfig::new()
+        .dimensions(3)
+        .n(10)
+        .initial_conditions(InitialConditions::UniformSphere);
+    check_invalid(config.clone(), "collisions");
+
+    let config = config.collisions(CollisionConfig {
+        enabled: false,
+        ..CollisionConfig::default()
+    });
+    assert!(config.build3().is_ok());
+    assert!(matches!(config.build(), Err(ConfigError::Invalid(_))));
+
+    check_invalid(config.clone().solver(SolverKind::Fmm), "solver");
+    check_invalid(config.clone().solver(SolverKind::Direct), "solver");
+    check_invalid(config.clone().opening(Opening::Bmax), "opening");
+    check_invalid(config.clone().box_guard(true), "box_guard");
+    check_invalid(config.clone().multipole(Multipole::Quadrupole), "multipole");
+    check_invalid(
+        config
+            .clone()
+            .adaptive_softening(AdaptiveSoftening::Density),
+        "adaptive_softening",
+    );
+    check_invalid(config.clone().dimensions(4), "dimensions");
+
+    let config = SimulationConfig::new().n(10);
+    assert!(matches!(config.build3(), Err(ConfigError::Invalid(_))));
+    check_invalid(
+        config.initial_conditions(InitialConditions::UniformSphere),
+        "initial_conditions",
+    );
+}
@@ -0,0 +1,75 @@
+use barnes_hut::{float::widen, Body, CollisionConfig, SimulationConfig, Softening, SolverKind};
+
+// Potential energy of every pair, summed directly in double precision
+fn potential_energy(bodies: &[Body], epsilon: f64) -> f64 {
+    let mut energy = 0.0;
+    for (i, a) in bodies.iter().enumerate() {
+        for b in &bodies[i + 1..] {
+            let d = b.pos - a.pos;
+            let d_sq = widen(d.x) * widen(d.x) + widen(d.y) * widen(d.y);
+            energy -= widen(a.mass) * widen(b.mass) / (d_sq + epsilon * epsilon).sqrt();
+        }
+    }
+    energy
+}
+
+// Diagnostics are usually taken before the first step too, which must not report a potential
+// energy of zero or anything other than what the solver would find
+fn check_initial_diagnostics(solver: SolverKind, tolerance: f64) {
+    let config = SimulationConfig::new()
+        .n(2000)
+        .seed(5)
+        .solver(solver)
+        .softening(Softening::Plummer)
+        .epsilon(1.0);
+    let simulation = config.build().unwrap();
+
+    let diagnostics = simulation.diagnostics();
+    assert_eq!(diagnostics.frame, 0);
+
+    let expected = potential_energy(&simulation.bodies, 1.0);
+    let error = (diagnostics.potential - expected).abs() / expected.abs();
+    assert!(
+        error < tolerance,
+        "{solver:?}: {} instead of {expected}",
+        diagnostics.potential
+    );
+}
+
+#[test]
+fn barnes_hut_diagnostics_before_the_first_step() {
+    check_initial_diagnostics(SolverKind::BarnesHut, 1e-2);
+}
+
+#[test]
+fn direct_sum_diagnostics_before_the_first_step() {
+    check_initial_diagnostics(SolverKind::Direct, 1e-5);
+}
+
+#[test]
+fn fmm_diagnostics_before_the_first_step() {
+    check_initial_diagnostics(SolverKind::Fmm, 1e-3);
+}
+
+#[test]
+fn octree_diagnostics_before_the_first_step() {
+    let collisions = CollisionConfig {
+        enabled: false,
+        ..CollisionConfig::default()
+    };
+    let config = SimulationConfig::new()
+        .dimensions(3)
+        .collisions(collisions)
+        .n(1000)
+        .seed(5);
+    let simulation = config.build3().unwrap();
+    let potential = simulation.diagnostics().potential;
+
+    let mut stepped = config.build3().unwrap();
+    stepped.dt = 0.0;
+    stepped.step();
+    let expected = stepped.diagnostics().potential;
+
+    assert!(expected < 0.0);
+    assert_eq!(potential, expected);
+}
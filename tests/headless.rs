@@ -0,0 +1,82 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+// Fresh output directory for one test
+fn output(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("headless-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn headless(args: &[&str]) {
+    let output = Command::new(env!("CARGO_BIN_EXE_headless"))
+        .args(["--n", "300", "--snapshot-every", "0", "--threads", "1"])
+        .args(["--integrator", "leapfrog", "--diagnostics-every", "2"])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+}
+
+// Frame and energy drift of every row
+fn rows(dir: &Path) -> Vec<(usize, f64)> {
+    let text = fs::read_to_string(dir.join("diagnostics.csv")).unwrap();
+    text.lines()
+        .skip(1)
+        .map(|line| {
+            let fields = line.split(',').collect::<Vec<_>>();
+            (fields[0].parse().unwrap(), fields[5].parse().unwrap())
+        })
+        .collect()
+}
+
+#[test]
+fn diagnostics_start_from_the_initial_state() {
+    let dir = output("initial");
+    headless(&["--steps", "6", "--output", dir.to_str().unwrap()]);
+
+    let rows = rows(&dir);
+    let frames = rows.iter().map(|&(frame, _)| frame).collect::<Vec<_>>();
+    assert_eq!(frames, [0, 2, 4, 6]);
+    assert_eq!(rows[0].1, 0.0);
+    assert!(rows[1..].iter().all(|&(_, drift)| drift != 0.0));
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn restarts_continue_the_diagnostics() {
+    let whole = output("whole");
+    headless(&["--steps", "8", "--output", whole.to_str().unwrap()]);
+
+    let split = output("split");
+    let split_dir = split.to_str().unwrap();
+    headless(&[
+        "--steps",
+        "4",
+        "--checkpoint-every",
+        "4",
+        "--output",
+        split_dir,
+    ]);
+    let checkpoint = split.join("checkpoint_000004.bin");
+    headless(&[
+        "--steps",
+        "8",
+        "--restart",
+        checkpoint.to_str().unwrap(),
+        "--output",
+        split_dir,
+    ]);
+
+    assert_eq!(
+        fs::read_to_string(split.join("diagnostics.csv")).unwrap(),
+        fs::read_to_string(whole.join("diagnostics.csv")).unwrap()
+    );
+
+    fs::remove_dir_all(whole).unwrap();
+    fs::remove_dir_all(split).unwrap();
+}
@@ -0,0 +1,171 @@
+use barnes_hut::{Catalog, ImportError};
+
+fn read_csv(text: &str) -> Result<Catalog, ImportError> {
+    Catalog::read_csv(text.as_bytes())
+}
+
+fn read_json(text: &str) -> Result<Catalog, ImportError> {
+    Catalog::read_json(text.as_bytes())
+}
+
+#[test]
+fn csv_fields_can_be_quoted() {
+    let catalog = read_csv(
+        "id,type,x,y,vx,vy,mass,radius\n\
+         3,\"star, giant\",1,2,0,0,1,0.5\n\
+         4,\" \"\"dark\"\" \",-1,-2,0,0,1,0.5\n\
+         5,,\"0\",0,0,0,1,0.5\n",
+    )
+    .unwrap();
+
+    assert_eq!(catalog.ids, [3, 4, 5]);
+    assert_eq!(
+        catalog.types,
+        [
+            Some("star, giant".to_string()),
+            Some(" \"dark\" ".to_string()),
+            None
+        ]
+    );
+    assert_eq!(catalog.bodies[1].pos.x, -1.0);
+}
+
+#[test]
+fn csv_skips_comments_and_blank_lines_alike() {
+    let catalog = read_csv(
+        "  # exported by hand\n\
+         x,y,vx,vy,mass,radius\n\
+         \t\n\
+         \t# indented comment\n\
+         1,2,0,0,1,0.5\n",
+    )
+    .unwrap();
+    assert_eq!(catalog.bodies.len(), 1);
+}
+
+#[test]
+fn csv_errors_have_line_numbers() {
+    let header = "# catalog\nid,x,y,vx,vy,mass,radius\n";
+
+    let error = read_csv(&format!("{header}1,0,0,0,0,1,1\n2,0,zero,0,0,1,1\n"))
+        .err()
+        .unwrap();
+    assert!(
+        matches!(
+            error,
+            ImportError::Invalid {
+                line: 4,
+                field: "y",
+                ..
+            }
+        ),
+        "{error}"
+    );
+
+    let error = read_csv("# catalog\n\nx,y,vx,vy,radius\n").err().unwrap();
+    assert!(
+        matches!(
+            error,
+            ImportError::MissingColumn {
+                line: 3,
+                column: "mass"
+            }
+        ),
+        "{error}"
+    );
+
+    let error = read_csv(&format!("{header}1,0,0,0,0,1,1\n\n2,0,0,0,0,1\n"))
+        .err()
+        .unwrap();
+    assert!(
+        matches!(
+            error,
+            ImportError::FieldCount {
+                line: 5,
+                expected: 7,
+                found: 6
+            }
+        ),
+        "{error}"
+    );
+
+    let error = read_csv(&format!(
+        "{header}1,0,0,0,0,1,1\n2,0,0,0,0,1,1\n1,0,0,0,0,1,1\n"
+    ))
+    .err()
+    .unwrap();
+    assert!(
+        matches!(error, ImportError::DuplicateId { line: 5, id: 1 }),
+        "{error}"
+    );
+    assert_eq!(error.to_string(), "line 5: duplicate id 1");
+
+    let error = read_csv(&format!("{header}1,0,0,0,0,1,\"1\n"))
+        .err()
+        .unwrap();
+    assert!(
+        matches!(error, ImportError::Unquoted { line: 3 }),
+        "{error}"
+    );
+}
+
+#[test]
+fn json_errors_have_line_numbers() {
+    let body = |id: u64, mass: &str| {
+        format!("  {{\n    \"id\": {id},\n    \"x\": 0, \"y\": 0, \"vx\": 0, \"vy\": 0,\n    \"mass\": {mass}, \"radius\": 1\n  }}")
+    };
+
+    // Objects start on lines 2, 7 and 12
+    let error = read_json(&format!(
+        "[\n{},\n{},\n{}\n]",
+        body(1, "1"),
+        body(2, "1"),
+        body(3, "-1")
+    ))
+    .err()
+    .unwrap();
+    assert!(
+        matches!(
+            error,
+            ImportError::Invalid {
+                line: 12,
+                field: "mass",
+                ..
+            }
+        ),
+        "{error}"
+    );
+
+    let error = read_json(&format!(
+        "[\n{},\n{},\n{}\n]",
+        body(1, "1"),
+        body(2, "1"),
+        body(1, "1")
+    ))
+    .err()
+    .unwrap();
+    assert!(
+        matches!(error, ImportError::DuplicateId { line: 12, id: 1 }),
+        "{error}"
+    );
+
+    // Parse errors come from `serde_json`, with its own position
+    let error = read_json(&format!("[\n{},\n{}\n]", body(1, "1"), body(2, "\"one\"")))
+        .err()
+        .unwrap();
+    match error {
+        ImportError::Json(error) => assert_eq!(error.line(), 10),
+        error => panic!("{error}"),
+    }
+
+    let error = read_json("[\n  {\"x\": 0, \"y\": 0, \"vx\": 0, \"vy\": 0, \"radius\": 1}\n]")
+        .err()
+        .unwrap();
+    match error {
+        ImportError::Json(error) => {
+            assert_eq!(error.line(), 2);
+            assert!(error.to_string().contains("mass"));
+        }
+        error => panic!("{error}"),
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::BTreeSet;
+
+use barnes_hut::{
+    float::{widen, Vec2},
+    models, Body, CollisionConfig, Float, IntegratorKind, Simulation, SimulationConfig, Softening,
+    SolverKind, TimestepConfig,
+};
+
+fn simulation(
+    bodies: Vec<Body>,
+    integrator: IntegratorKind,
+    dt: Float,
+    epsilon: Float,
+) -> Simulation {
+    SimulationConfig::new()
+        .solver(SolverKind::Direct)
+        .softening(Softening::Plummer)
+        .epsilon(epsilon)
+        .dt(dt)
+        .integrator(integrator)
+        .collisions(CollisionConfig {
+            enabled: false,
+            ..CollisionConfig::default()
+        })
+        .build_with(bodies)
+        .unwrap()
+}
+
+// Largest relative change of the total energy from its initial value over `steps` steps
+fn energy_drift(mut simulation: Simulation, steps: usize) -> f64 {
+    let initial = simulation.diagnostics().total_energy();
+    let mut drift: f64 = 0.0;
+    for _ in 0..steps {
+        simulation.step();
+        let energy = simulation.diagnostics().total_energy();
+        drift = drift.max(((energy - initial) / initial).abs());
+    }
+    drift
+}
+
+// Two equal masses on an eccentric orbit with a period of about 6.8, three orbits in 2000 steps
+// of 0.01
+fn binary() -> Vec<Body> {
+    vec![
+        Body::new(Vec2::new(-1.0, 0.0), Vec2::new(0.0, -0.35), 1.0, 0.0),
+        Body::new(Vec2::new(1.0, 0.0), Vec2::new(0.0, 0.35), 1.0, 0.0),
+    ]
+}
+
+// About one crossing time in 1000 steps of 0.05
+fn cluster() -> Vec<Body> {
+    models::plummer(64, 100000.0, 300.0, 7)
+}
+
+// The second-order integrators keep the energy within a small bound that the semi-implicit Euler
+// integrator, which is only first order, doesn't come close to
+fn check_energy_conservation(integrator: IntegratorKind) {
+    let euler = IntegratorKind::SemiImplicitEuler;
+    let bound = energy_drift(simulation(binary(), euler, 0.01, 0.01), 2000) / 10.0;
+
+    let drift = energy_drift(simulation(binary(), integrator, 0.01, 0.01), 2000);
+    assert!(
+        drift < bound.min(1e-3),
+        "{integrator:?}: binary drifted {drift:e}"
+    );
+
+    let drift = energy_drift(simulation(cluster(), integrator, 0.05, 20.0), 1000);
+    assert!(drift < 1e-4, "{integrator:?}: cluster drifted {drift:e}");
+}
+
+#[test]
+fn leapfrog_conserves_energy() {
+    check_energy_conservation(IntegratorKind::Leapfrog);
+}
+
+#[test]
+fn velocity_verlet_conserves_energy() {
+    check_energy_conservation(IntegratorKind::VelocityVerlet);
+}
+
+#[test]
+fn block_leapfrog_conserves_energy() {
+    let block = IntegratorKind::BlockLeapfrog;
+    check_energy_conservation(block);
+
+    // With a step too large for the pericenter, the bodies move down to the timesteps they need
+    // there instead of losing energy like leapfrog does
+    let leapfrog = IntegratorKind::Leapfrog;
+    let bound = energy_drift(simulation(binary(), leapfrog, 0.16, 0.01), 125) / 10.0;
+    let drift = energy_drift(simulation(binary(), block, 0.16, 0.01), 125);
+    assert!(drift < bound.min(1e-3), "binary drifted {drift:e}");
+}
+
+// Every step ends with all bodies synchronized, each at the largest timestep `dt / 2^level` below
+// `sqrt(2 eta epsilon / |a|)` for its new acceleration
+#[test]
+fn block_levels_follow_the_acceleration_criterion() {
+    let (dt, epsilon) = (1.0, 5.0);
+    let bodies = models::plummer(256, 100000.0, 300.0, 3);
+    let mut simulation = simulation(bodies, IntegratorKind::BlockLeapfrog, dt, epsilon);
+    let TimestepConfig { max_level, eta } = TimestepConfig::default();
+
+    let mut levels = BTreeSet::new();
+    for _ in 0..20 {
+        simulation.step();
+        for body in &simulation.bodies {
+            let ideal = (2.0 * widen(eta * epsilon) / widen(body.acc.mag())).sqrt();
+            let level_dt = |level: u32| widen(dt) / 2.0_f64.powi(level as i32);
+            let level = body.level;
+
+            assert!(
+                level_dt(level) <= ideal * (1.0 + 1e-4) || level == max_level,
+                "level {level} is too coarse for a timestep of {ideal}"
+            );
+            assert!(
+                level == 0 || level_dt(level - 1) > ideal * (1.0 - 1e-4),
+                "level {level} is finer than a timestep of {ideal} needs"
+            );
+            levels.insert(level);
+        }
+    }
+    assert!(levels.len() > 2, "{levels:?}");
+}
@@ -0,0 +1,71 @@
+use barnes_hut::{IntegratorKind, Simulation, SimulationConfig, Snapshot};
+
+// Everything a checkpoint holds, as written to disk
+fn checkpoint(simulation: &Simulation) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    simulation.snapshot().write(&mut bytes).unwrap();
+    bytes
+}
+
+// Runs `before` steps, restarts from a checkpoint and runs `after` more, which must give the same
+// bytes as running all of them at once
+fn check_restart(integrator: IntegratorKind) {
+    let config = SimulationConfig::new()
+        .n(300)
+        .seed(7)
+        .integrator(integrator);
+    let (before, after) = (6, 5);
+
+    let mut uninterrupted = config.build().unwrap();
+    for _ in 0..before + after {
+        uninterrupted.step();
+    }
+
+    let mut interrupted = config.build().unwrap();
+    for _ in 0..before {
+        interrupted.step();
+    }
+    let saved = checkpoint(&interrupted);
+
+    let mut restarted = Simulation::from_snapshot(Snapshot::read(&saved[..]).unwrap()).unwrap();
+    assert_eq!(checkpoint(&restarted), saved);
+    for _ in 0..after {
+        restarted.step();
+    }
+
+    assert_eq!(restarted.frame, uninterrupted.frame);
+    assert_eq!(checkpoint(&restarted), checkpoint(&uninterrupted));
+}
+
+#[test]
+fn leapfrog_restarts_bit_for_bit() {
+    check_restart(IntegratorKind::Leapfrog);
+}
+
+#[test]
+fn velocity_verlet_restarts_bit_for_bit() {
+    check_restart(IntegratorKind::VelocityVerlet);
+}
+
+#[test]
+fn block_leapfrog_restarts_bit_for_bit() {
+    check_restart(IntegratorKind::BlockLeapfrog);
+}
+
+#[test]
+fn catalog_ids_survive_a_checkpoint() {
+    let simulation = SimulationConfig::new().n(10).build().unwrap();
+    let mut snapshot = simulation.snapshot();
+    snapshot.ids = (100..110).rev().collect();
+
+    let mut bytes = Vec::new();
+    snapshot.write(&mut bytes).unwrap();
+    let read = Snapshot::read(&bytes[..]).unwrap();
+    assert_eq!(read.ids, snapshot.ids);
+
+    // Ids are all or nothing
+    snapshot.ids.pop();
+    let mut bytes = Vec::new();
+    snapshot.write(&mut bytes).unwrap();
+    assert!(Snapshot::read(&bytes[..]).is_err());
+}
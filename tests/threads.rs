@@ -0,0 +1,49 @@
+use barnes_hut::{float::widen, utils, Body, Construction, SimulationConfig, SolverKind};
+
+// Accelerations and potentials of `bodies` computed with `threads` threads
+fn forces(config: &SimulationConfig, bodies: &[Body], threads: usize) -> Vec<[u64; 3]> {
+    let mut bodies = bodies.to_vec();
+    let mut solver = config.solver.build(config);
+    solver.build(&mut bodies, threads);
+    solver.attract(&mut bodies, &|_| true, threads);
+    let potentials = solver.potentials(&bodies, threads).unwrap();
+
+    bodies
+        .iter()
+        .zip(potentials)
+        .map(|(body, potential)| {
+            [body.acc.x, body.acc.y, potential].map(|value| widen(value).to_bits())
+        })
+        .collect()
+}
+
+// Chunks are handed out to whichever thread is free, so the thread count must not change any bit
+fn check_threads(config: SimulationConfig, n: usize) {
+    let bodies = utils::uniform_disc(n, 3);
+    let serial = forces(&config, &bodies, 1);
+    assert!(serial.iter().any(|&[x, _, _]| x != 0));
+    assert_eq!(forces(&config, &bodies, 4), serial);
+}
+
+#[test]
+fn barnes_hut_is_independent_of_threads() {
+    check_threads(SimulationConfig::new(), 20000);
+}
+
+#[test]
+fn barnes_hut_morton_is_independent_of_threads() {
+    let config = SimulationConfig::new()
+        .construction(Construction::Morton)
+        .leaf_capacity(8);
+    check_threads(config, 20000);
+}
+
+#[test]
+fn direct_sum_is_independent_of_threads() {
+    check_threads(SimulationConfig::new().solver(SolverKind::Direct), 2000);
+}
+
+#[test]
+fn fmm_is_independent_of_threads() {
+    check_threads(SimulationConfig::new().solver(SolverKind::Fmm), 20000);
+}